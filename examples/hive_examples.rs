@@ -5,7 +5,7 @@ use std::fs::File;
 fn get_root_node() {
     let file = File::open(".testdata/NTUSER.DAT").unwrap();
 
-    let mut hive = match Hive::from_source(file){
+    let hive = match Hive::from_source(file){
         Ok(h) => h,
         Err(e) => panic!(e)
     };
@@ -4,11 +4,13 @@ extern crate env_logger;
 extern crate clap;
 extern crate rwinreg;
 use rwinreg::hive;
+use rwinreg::record::{Record,DeletedRecord,SecurityRecord};
+use rwinreg::vk::{BinaryEncoding,DecodeMode};
 use clap::{App, Arg};
 use std::fs;
 use std::fs::File;
 
-fn process_directory(directory: &str) {
+fn process_directory(directory: &str, keys: bool, deleted: bool, verify: bool, security_ids: bool, binary_encoding: BinaryEncoding, decode_mode: DecodeMode) {
     for dir_reader in fs::read_dir(directory) {
         for entry_result in dir_reader {
             match entry_result {
@@ -20,7 +22,7 @@ fn process_directory(directory: &str) {
                             Ok(result) => {
                                 match result {
                                     true => {
-                                        process_file(&path_string);
+                                        process_file(&path_string, keys, deleted, verify, security_ids, binary_encoding, decode_mode);
                                     },
                                     false => {
                                         debug!("{} is not a hive file.",path_string);
@@ -33,7 +35,7 @@ fn process_directory(directory: &str) {
                         }
                     } else if path.is_dir(){
                         let path_string = path.into_os_string().into_string().unwrap();
-                        process_directory(&path_string);
+                        process_directory(&path_string, keys, deleted, verify, security_ids, binary_encoding, decode_mode);
                     }
                 },
                 Err(error) => {
@@ -44,7 +46,29 @@ fn process_directory(directory: &str) {
     }
 }
 
-fn process_file(filename: &str) -> bool {
+fn print_record(filename: &str, mut record: Record, binary_encoding: BinaryEncoding, decode_mode: DecodeMode) {
+    record.valuekey.set_binary_encoding(binary_encoding);
+    record.valuekey.set_decode_mode(decode_mode);
+
+    match serde_json::to_string(&record) {
+        Ok(json_str) => println!("{}",json_str),
+        Err(error) => error!("{} [error: {}]", filename, error)
+    }
+}
+
+fn print_deleted_record(filename: &str, mut record: DeletedRecord, binary_encoding: BinaryEncoding, decode_mode: DecodeMode) {
+    if let Some(ref mut vk) = record.valuekey {
+        vk.set_binary_encoding(binary_encoding);
+        vk.set_decode_mode(decode_mode);
+    }
+
+    match serde_json::to_string(&record) {
+        Ok(json_str) => println!("{}",json_str),
+        Err(error) => error!("{} [error: {}]", filename, error)
+    }
+}
+
+fn process_file(filename: &str, keys: bool, deleted: bool, verify: bool, security_ids: bool, binary_encoding: BinaryEncoding, decode_mode: DecodeMode) -> bool {
     info!("processing file: {}",filename);
 
     let hive_fh = match File::open(filename){
@@ -55,7 +79,7 @@ fn process_file(filename: &str) -> bool {
         }
     };
 
-    let mut hive = match hive::Hive::from_source(hive_fh) {
+    let hive = match hive::Hive::from_source(hive_fh) {
         Ok(hive) => hive,
         Err(error) => {
             error!("{} [error: {}]", filename, error);
@@ -63,28 +87,99 @@ fn process_file(filename: &str) -> bool {
         }
     };
 
-    loop {
-        let record = match hive.get_next_value(){
-            Ok(option) => {
-                match option {
-                    Some(record) => record,
-                    None => {
-                        break;
+    if verify {
+        let report = hive.verify();
+        for issue in report.issues.iter() {
+            match serde_json::to_string(&issue) {
+                Ok(json_str) => println!("{}",json_str),
+                Err(error) => error!("{} [error: {}]", filename, error)
+            }
+        }
+        return report.is_clean();
+    }
+
+    // Print each descriptor once for the records to refer to
+    let mut security_ids = security_ids;
+    if security_ids {
+        match hive.security_keys() {
+            Ok(security_keys) => {
+                for mismatch in security_keys.reference_count_mismatches() {
+                    warn!("{} [security key at offset {} has a reference count of {} but {} keys point at it]",
+                        filename, mismatch.offset, mismatch.reference_count, mismatch.key_count
+                    );
+                }
+                for unlisted in security_keys.unlisted_keys() {
+                    warn!("{} [key {} points at offset {} which is not on the security key list]",
+                        filename, unlisted.path, unlisted.security_id
+                    );
+                }
+                for &offset in security_keys.get_offsets() {
+                    let security_record = SecurityRecord::new(offset, security_keys.get(offset).unwrap());
+                    match serde_json::to_string(&security_record) {
+                        Ok(json_str) => println!("{}",json_str),
+                        Err(error) => error!("{} [error: {}]", filename, error)
                     }
                 }
             },
             Err(error) => {
-                panic!("error: {}",error);
+                error!("{} [error: {}; embedding security descriptors instead]", filename, error);
+                security_ids = false;
+            }
+        }
+    }
+
+    if keys {
+        let mut key_records = hive.key_records().lenient();
+        if security_ids {
+            key_records = key_records.security_ids();
+        }
+        for result in key_records.by_ref() {
+            let record = match result {
+                Ok(record) => record,
+                Err(error) => {
+                    error!("{} [error: {}]", filename, error);
+                    continue;
+                }
+            };
+            match serde_json::to_string(&record) {
+                Ok(json_str) => println!("{}",json_str),
+                Err(error) => error!("{} [error: {}]", filename, error)
+            }
+        }
+
+        for diagnostic in key_records.get_diagnostics() {
+            warn!("{} [skipped {:?} at offset {} under {}: {}]",
+                filename, diagnostic.kind, diagnostic.offset, diagnostic.path, diagnostic.message
+            );
+        }
+    }
+
+    let mut records = hive.records().lenient();
+    if security_ids {
+        records = records.security_ids();
+    }
+    for result in records.by_ref() {
+        let record = match result {
+            Ok(record) => record,
+            Err(error) => {
+                error!("{} [error: {}]", filename, error);
+                continue;
             }
         };
-        let json_str = serde_json::to_string(&record).unwrap();
-        println!("{}",json_str);
+        print_record(filename, record, binary_encoding, decode_mode);
     }
 
-    // for value in hive {
-    //     let json_str = serde_json::to_string(&value).unwrap();
-    //     println!("{}",json_str);
-    // }
+    for diagnostic in records.get_diagnostics() {
+        warn!("{} [skipped {:?} at offset {} under {}: {}]",
+            filename, diagnostic.kind, diagnostic.offset, diagnostic.path, diagnostic.message
+        );
+    }
+
+    if deleted {
+        for record in hive.get_deleted_records() {
+            print_deleted_record(filename, record, binary_encoding, decode_mode);
+        }
+    }
 
     return true;
 }
@@ -108,18 +203,63 @@ fn main() {
         .required_unless("pipe")
         .takes_value(true);
 
+    let keys_arg = Arg::with_name("keys")
+        .short("k")
+        .long("keys")
+        .help("Also output a record for every key, including keys without values.");
+
+    let deleted_arg = Arg::with_name("deleted")
+        .short("d")
+        .long("deleted")
+        .help("Also output deleted keys and values recovered from unallocated cells.");
+
+    let verify_arg = Arg::with_name("verify")
+        .short("v")
+        .long("verify")
+        .help("Check the structure of the hive and output the issues found instead of records.");
+
+    let security_ids_arg = Arg::with_name("security_ids")
+        .long("security-ids")
+        .help("Output each security descriptor once and refer to it by security_id in records.");
+
+    let base64_arg = Arg::with_name("base64")
+        .long("base64")
+        .help("Output binary value data as base64 instead of hex.");
+
+    let checked_arg = Arg::with_name("checked")
+        .long("checked")
+        .help("Decode value data that does not match its type as far as possible, with warnings and any leftover bytes, instead of failing.");
+
     let options = App::new("reg_parser")
         .version("for debug")
         .author("Matthew Seyer <https://github.com/forensicmatt/r-winreg>")
         .about("Registry Parser written in Rust.")
         .arg(source_arg)
+        .arg(keys_arg)
+        .arg(deleted_arg)
+        .arg(verify_arg)
+        .arg(security_ids_arg)
+        .arg(base64_arg)
+        .arg(checked_arg)
         .get_matches();
 
     let source = options.value_of("source").unwrap();
+    let keys = options.is_present("keys");
+    let deleted = options.is_present("deleted");
+    let verify = options.is_present("verify");
+    let security_ids = options.is_present("security_ids");
+    let binary_encoding = match options.is_present("base64") {
+        true => BinaryEncoding::Base64,
+        false => BinaryEncoding::Hex
+    };
+    let decode_mode = match options.is_present("checked") {
+        true => DecodeMode::Checked,
+        false => DecodeMode::Strict
+    };
 
     if is_directory(source) {
-        process_directory(source);
+        process_directory(source, keys, deleted, verify, security_ids, binary_encoding, decode_mode);
     } else {
-        process_file(source);
+        process_file(source, keys, deleted, verify, security_ids, binary_encoding, decode_mode);
     }
 }
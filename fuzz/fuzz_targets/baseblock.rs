@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::baseblock::BaseBlock;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() >= 4096 {
+        let mut buffer = [0; 4096];
+        buffer.copy_from_slice(&data[0..4096]);
+        let _ = BaseBlock::new(&buffer, 0);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::cell::Cell;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cell) = Cell::at_offset(&mut Cursor::new(data), 0) {
+        let _ = cell.get_data();
+    }
+    let _ = Cell::new(&mut Cursor::new(data), 0);
+});
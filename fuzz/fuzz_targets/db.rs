@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::db::DataBlock;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(db) = DataBlock::new(data, 0) {
+        let _ = db.get_data(&mut Cursor::new(data));
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::hive::Hive;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(hive) = Hive::from_source(Cursor::new(data)) {
+        let _ = hive.open_key("Software\\Microsoft");
+        for _ in hive.key_records().lenient() {}
+        for _ in hive.records().lenient() {}
+        let _ = hive.get_deleted_records();
+        let _ = hive.get_slack();
+    }
+});
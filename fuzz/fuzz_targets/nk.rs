@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::nk::NodeKey;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut nk) = NodeKey::new(data, 0) {
+        let mut reader = Cursor::new(data);
+        let _ = nk.get_sub_key_offsets(&mut reader);
+        let _ = nk.get_value_offsets(&mut reader);
+        let _ = nk.find_sub_key(&mut reader, "Software");
+        let _ = nk.read_class_name(&mut reader);
+        let _ = nk.set_security_key(&mut reader);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::resource;
+use rwinreg::resource::ResourceRequirementsList;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = resource::read_resource_list(data);
+    let _ = resource::read_full_resource_descriptor(data);
+    let _ = ResourceRequirementsList::new(data);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::ri::RootIndex;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut list) = RootIndex::new(data, 0) {
+        let mut reader = Cursor::new(data);
+        let _ = list.find_key(&mut reader, "Software");
+        while let Ok(Some(_)) = list.get_next_key(&mut reader) {}
+    }
+});
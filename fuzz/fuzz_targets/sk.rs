@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::sk::SecurityKey;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = SecurityKey::new(data, 0);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::transaction_log::{TransactionLog,LogEntry,DirtyVector};
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = LogEntry::new(data, 0);
+    let _ = DirtyVector::new(data, 0, 65536);
+    let _ = TransactionLog::from_source(Cursor::new(data));
+});
@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rwinreg;
+use rwinreg::vk::ValueKey;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut vk) = ValueKey::new(data, 0) {
+        let _ = vk.decode_data();
+        let _ = vk.read_value(&mut Cursor::new(data));
+        let _ = vk.decode_data();
+    }
+});
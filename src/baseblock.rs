@@ -1,7 +1,77 @@
 use rwinstructs::timestamp::{WinTimestamp};
 use byteorder::{ByteOrder,LittleEndian};
 use errors::RegError;
+use cell::CellData;
 use utils;
+use std::fmt;
+
+/// The hive format version of a base block. Some structures only exist from a given
+/// minor version on: lf lists from 1.3, db records from 1.4, lh lists from 1.5 and
+/// layered key fields from 1.6.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct HiveVersion {
+    pub major: u32,
+    pub minor: u32
+}
+impl HiveVersion {
+    pub fn new(major: u32, minor: u32) -> HiveVersion {
+        HiveVersion {
+            major: major,
+            minor: minor
+        }
+    }
+
+    /// The newest version, which has every structure. Used for structures read on their own.
+    pub fn latest() -> HiveVersion {
+        HiveVersion::new(1, 6)
+    }
+
+    /// Versions 1.1 through 1.6 are known.
+    pub fn is_supported(&self)->bool{
+        self.major == 1 && self.minor >= 1 && self.minor <= 6
+    }
+
+    pub fn has_fast_leaves(&self)->bool{
+        self.minor >= 3
+    }
+
+    pub fn has_big_data(&self)->bool{
+        self.minor >= 4
+    }
+
+    pub fn has_hash_leaves(&self)->bool{
+        self.minor >= 5
+    }
+
+    pub fn has_layered_keys(&self)->bool{
+        self.minor >= 6
+    }
+
+    /// Check that a subkey list is one this version has.
+    pub fn check_sub_key_list(&self, cell_data: &CellData, offset: u64)->Result<(),RegError>{
+        let supported = match *cell_data {
+            CellData::FastLeaf(_) => self.has_fast_leaves(),
+            CellData::HashLeaf(_) => self.has_hash_leaves(),
+            _ => true
+        };
+
+        if !supported {
+            return Err(
+                RegError::version_mismatch(
+                    format!("{} list at offset {} is not valid in a version {} hive.",
+                        cell_data.get_type_name(), offset, self
+                    )
+                )
+            );
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for HiveVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}.{}",self.major,self.minor)
+    }
+}
 
 #[derive(Serialize,Debug)]
 pub struct BaseBlock {
@@ -30,7 +100,7 @@ pub struct BaseBlock {
     boot_recover: u32
 }
 
-fn compute_checksum(buffer: &[u8;4096])->u32 {
+pub fn compute_checksum(buffer: &[u8;4096])->u32 {
     let mut chk: u32 = 0;
     // compute over first 508 bytes of block
     for i in 0..127 {
@@ -108,9 +178,35 @@ impl BaseBlock {
         self._calculated_checksum == self.checksum
     }
 
+    /// A hive is dirty when the last write to it did not complete and the
+    /// transaction logs hold data that is not in the primary file.
+    pub fn is_dirty(&self)->bool {
+        self.primary_seq_num != self.secondary_seq_num
+    }
+
+    pub fn primary_seq_num(&self)->u32{
+        self.primary_seq_num
+    }
+
+    pub fn secondary_seq_num(&self)->u32{
+        self.secondary_seq_num
+    }
+
+    pub fn file_type(&self)->u32{
+        self.file_type
+    }
+
     pub fn root_cell_offset(&self)->u32{
         self.root_cell_offset
     }
+
+    pub fn hive_bins_data_size(&self)->u32{
+        self.hive_bins_data_size
+    }
+
+    pub fn get_version(&self)->HiveVersion{
+        HiveVersion::new(self.major_version, self.minor_version)
+    }
 }
 
 #[cfg(test)]
@@ -151,5 +247,15 @@ mod tests {
         assert_eq!(baseblock.boot_recover, 0);
 
         assert_eq!(baseblock.verified(), true);
+        assert_eq!(baseblock.is_dirty(), true);
+
+        let version = baseblock.get_version();
+        assert_eq!(format!("{}",version), "1.3");
+        assert!(version.is_supported());
+        assert!(version.has_fast_leaves());
+        assert!(!version.has_big_data());
+        assert!(!version.has_hash_leaves());
+        assert!(!version.has_layered_keys());
+        assert!(!HiveVersion::new(1, 7).is_supported());
     }
 }
@@ -7,6 +7,7 @@ use lh::HashLeaf;
 use li::IndexLeaf;
 use ri::RootIndex;
 use db::DataBlock;
+use hbin::{HiveBinIterator,HBIN_HEADER_SIZE};
 use errors::RegError;
 use serde::ser;
 use std::io::Read;
@@ -27,6 +28,23 @@ pub enum CellData{
     DataBlock(DataBlock)
 }
 
+impl CellData {
+    /// The signature of the cell type, or raw for cells without a known signature.
+    pub fn get_type_name(&self)->&'static str{
+        match *self {
+            CellData::Raw(_) => "raw",
+            CellData::IndexLeaf(_) => "li",
+            CellData::FastLeaf(_) => "lf",
+            CellData::HashLeaf(_) => "lh",
+            CellData::RootIndex(_) => "ri",
+            CellData::NodeKey(_) => "nk",
+            CellData::ValueKey(_) => "vk",
+            CellData::SecurityKey(_) => "sk",
+            CellData::DataBlock(_) => "db"
+        }
+    }
+}
+
 pub struct CellSignature(u16);
 impl CellSignature {
     pub fn new(value: u16) -> CellSignature {
@@ -79,10 +97,28 @@ pub struct Cell{
 impl Cell {
     pub fn new<R: Read>(reader: &mut R, offset: u64) -> Result<Cell,RegError> {
         let size = reader.read_i32::<LittleEndian>()?;
-        let mut data = vec![0; (size.abs() - 4) as usize];
-        reader.read_exact(
-            data.as_mut_slice()
-        )?;
+
+        // A cell holds at least its size and a signature
+        let cell_size = (size as i64).abs();
+        if cell_size < 8 {
+            return Err(
+                RegError::out_of_bounds(
+                    format!("Cell at offset {} has an invalid size: {}", offset, size)
+                )
+            );
+        }
+
+        // Read through take() so a bogus size cannot allocate more than the source holds
+        let data_size = (cell_size - 4) as u64;
+        let mut data = Vec::new();
+        reader.take(data_size).read_to_end(&mut data)?;
+        if (data.len() as u64) < data_size {
+            return Err(
+                RegError::out_of_bounds(
+                    format!("Cell at offset {} with size {} extends past the end of the source.", offset, size)
+                )
+            );
+        }
 
         Ok(
             Cell {
@@ -120,20 +156,19 @@ impl Cell {
         reader.seek(
             SeekFrom::Start(offset)
         )?;
-        let size = reader.read_i32::<LittleEndian>()?;
-        debug!("cell at offset {} with size {}",offset,size);
-        let mut data = vec![0; (size.abs() - 4) as usize];
-        reader.read_exact(
-            data.as_mut_slice()
-        )?;
+        debug!("cell at offset {}",offset);
 
-        Ok(
-            Cell {
-                _offset: offset,
-                size: size,
-                data: data
-            }
-        )
+        Cell::new(reader, offset)
+    }
+
+    /// The absolute offset of the cell in the hive.
+    pub fn get_offset(&self)->u64{
+        self._offset
+    }
+
+    /// A negative cell size means the cell is allocated.
+    pub fn is_allocated(&self)->bool{
+        self.size < 0
     }
 
     pub fn get_signature(&self)->CellSignature{
@@ -218,9 +253,110 @@ impl Cell {
     }
 }
 
+/// Walks every cell of every hive bin in order, allocated or not.
+///
+/// Each cell is returned along with its decoded CellData. Unallocated cells that fail
+/// to decode are returned as CellData::Raw because free space holds no guarantees. A cell
+/// with an invalid size ends the walk of its hive bin, which is reported as an error.
+pub struct CellIterator<'a, Rs: 'a> {
+    hive_bins: HiveBinIterator<'a, Rs>,
+    buffer: Vec<u8>,
+    buffer_offset: u64,
+    position: usize
+}
+impl <'a, Rs: Read+Seek> CellIterator<'a, Rs> {
+    pub fn new(hive_bins: HiveBinIterator<'a, Rs>) -> CellIterator<'a, Rs> {
+        CellIterator {
+            hive_bins: hive_bins,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            position: 0
+        }
+    }
+
+    fn next_cell(&mut self)->Result<(Cell,CellData),RegError> {
+        let offset = self.buffer_offset + self.position as u64;
+        let remaining = self.buffer.len() - self.position;
+        if remaining < 4 {
+            self.position = self.buffer.len();
+            return Err(
+                RegError::validation_error(
+                    format!("Cell at offset {} has no room for a size.", offset)
+                )
+            );
+        }
+
+        let size = LittleEndian::read_i32(&self.buffer[self.position..self.position + 4]);
+        let cell_size = (size as i64).abs() as usize;
+        if cell_size < 8 || cell_size % 8 != 0 || cell_size > remaining {
+            // We cannot find the next cell in this hive bin
+            self.position = self.buffer.len();
+            return Err(
+                RegError::validation_error(
+                    format!("Cell at offset {} has an invalid size: {}", offset, size)
+                )
+            );
+        }
+
+        let cell = Cell {
+            _offset: offset,
+            size: size,
+            data: self.buffer[self.position + 4..self.position + cell_size].to_vec()
+        };
+        self.position += cell_size;
+
+        let cell_data = match cell.get_data() {
+            Ok(cell_data) => cell_data,
+            Err(error) => {
+                if cell.is_allocated() {
+                    return Err(error);
+                }
+                cell.get_raw_data()
+            }
+        };
+
+        Ok((cell, cell_data))
+    }
+}
+impl <'a, Rs: Read+Seek> Iterator for CellIterator<'a, Rs> {
+    type Item = Result<(Cell,CellData),RegError>;
+
+    fn next(&mut self) -> Option<Result<(Cell,CellData),RegError>> {
+        loop {
+            if self.position < self.buffer.len() {
+                return Some(self.next_cell());
+            }
+
+            let hive_bin = match self.hive_bins.next() {
+                Some(Ok(hive_bin)) => hive_bin,
+                Some(Err(error)) => return Some(Err(error)),
+                None => return None
+            };
+
+            if !hive_bin.is_valid() {
+                return Some(
+                    Err(
+                        RegError::validation_error(
+                            format!("Invalid hive bin at offset {}.", hive_bin.get_offset())
+                        )
+                    )
+                );
+            }
+
+            self.buffer = match self.hive_bins.read_cell_buffer(&hive_bin) {
+                Ok(buffer) => buffer,
+                Err(error) => return Some(Err(error))
+            };
+            self.buffer_offset = hive_bin.get_offset() + HBIN_HEADER_SIZE as u64;
+            self.position = 0;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use errors::ErrorKind;
     use std::io::Cursor;
     use std::io::Read;
     use std::fs::File;
@@ -250,4 +386,16 @@ mod tests {
         ];
         assert_eq!(&cell.data[..], known_data);
     }
+
+    #[test]
+    fn cell_invalid_size() {
+        // Edge case sizes that would overflow or ask for huge buffers
+        for size in [i32::min_value(), -1, 0, 4, 7, -0x7FFFFFF8].iter() {
+            let mut buffer = vec![0; 16];
+            LittleEndian::write_i32(&mut buffer[0..4], *size);
+
+            assert_eq!(Cell::new(&mut Cursor::new(&buffer),0).err().unwrap().kind, ErrorKind::OutOfBounds);
+            assert_eq!(Cell::at_offset(&mut Cursor::new(&buffer),0).err().unwrap().kind, ErrorKind::OutOfBounds);
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use byteorder::{ByteOrder,ReadBytesExt,LittleEndian};
+use utils;
 use errors::RegError;
 use hive::HBIN_START_OFFSET;
 use cell::Cell;
@@ -6,6 +7,9 @@ use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 
+// The most data a single db segment holds
+pub const DB_SEGMENT_SIZE: u32 = 16344;
+
 // db
 #[derive(Serialize, Debug)]
 pub struct DataBlock{
@@ -17,6 +21,8 @@ pub struct DataBlock{
 }
 impl DataBlock{
     pub fn new(buffer: &[u8], offset: u64)->Result<DataBlock,RegError> {
+        utils::check_len(buffer, 8, "DataBlock", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let segment_count = LittleEndian::read_u16(&buffer[2..4]);
         let segments_offset = LittleEndian::read_u32(&buffer[4..8]);
@@ -31,10 +37,15 @@ impl DataBlock{
         )
     }
 
+    /// Get the data of all segments. Every segment but the last holds exactly
+    /// DB_SEGMENT_SIZE bytes of data, so only the last segment can add slack.
     pub fn get_data<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Vec<u8>,RegError> {
-        // This data could include slack!
-        let mut raw_data: Vec<u8> = Vec::new();
+        let segments_list = self.get_segment_offsets(reader)?;
+        self.read_segments(reader, &segments_list)
+    }
 
+    /// Get the offsets of the segment cells, relative to the first hive bin.
+    pub fn get_segment_offsets<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Vec<u32>,RegError> {
         // Seek to the list offset
         reader.seek(
             SeekFrom::Start(HBIN_START_OFFSET + self.segments_offset as u64)
@@ -44,7 +55,16 @@ impl DataBlock{
         //The segment_list is a cell in itself of raw data.
         // the first 4 bytes are the cell size, followed by the offset list. This mean that
         // data padding in the list is possible to get though not currently handled
-        let _list_cell_size = reader.read_i32::<LittleEndian>()?;
+        let list_cell_size = reader.read_i32::<LittleEndian>()?;
+        if ((list_cell_size as i64).abs() - 4) / 4 < self.segment_count as i64 {
+            return Err(
+                RegError::size_mismatch(
+                    format!("DataBlock at offset {} has {} segments but a segment list cell size of {}.",
+                        self._offset, self.segment_count, list_cell_size
+                    )
+                )
+            );
+        }
 
         // read offsets into the segments_list
         for i in 0..self.segment_count {
@@ -55,12 +75,26 @@ impl DataBlock{
             );
         }
 
-        for segment_offset in segments_list {
+        Ok(segments_list)
+    }
+
+    /// Read the data of the segment cells at the given offsets, dropping the padding
+    /// of all but the last.
+    pub fn read_segments<Rs: Read+Seek>(&self, reader: &mut Rs, segments_list: &[u32])->Result<Vec<u8>,RegError> {
+        let mut raw_data: Vec<u8> = Vec::new();
+
+        let segment_count = segments_list.len();
+        for (i, &segment_offset) in segments_list.iter().enumerate() {
             // Read cell
             let mut cell = Cell::at_offset(
                 reader, segment_offset as u64 + HBIN_START_OFFSET
             )?;
 
+            // Drop the alignment padding of full segments
+            if i + 1 < segment_count {
+                cell.data.truncate(DB_SEGMENT_SIZE as usize);
+            }
+
             raw_data.append(
                 &mut cell.data
             );
@@ -0,0 +1,238 @@
+use byteorder::{ByteOrder,LittleEndian};
+use hive::HBIN_START_OFFSET;
+use cell::Cell;
+use cell::CellData;
+use cell::CellIterator;
+use nk::NodeKey;
+use baseblock::HiveVersion;
+use vk::ValueKey;
+use record::{DeletedRecord,UNKNOWN_PATH};
+use std::collections::{BTreeMap,HashSet};
+use std::io::{Read,Seek};
+
+/// Node keys and value keys found in the unallocated cells of a hive,
+/// keyed by their cell offset relative to the first hive bin.
+#[derive(Debug)]
+pub struct DeletedCells {
+    keys: BTreeMap<u32,NodeKey>,
+    values: BTreeMap<u32,ValueKey>
+}
+impl DeletedCells {
+    pub fn from_cells<'a, Rs: Read+Seek>(cells: CellIterator<'a, Rs>) -> DeletedCells {
+        let mut keys = BTreeMap::new();
+        let mut values = BTreeMap::new();
+
+        for result in cells {
+            let (cell, cell_data) = match result {
+                Ok(entry) => entry,
+                Err(error) => {
+                    warn!("Skipping cells while scanning for deleted entries: {}",error);
+                    continue;
+                }
+            };
+
+            if cell.is_allocated() {
+                continue;
+            }
+
+            let mut found = vec![(cell.get_offset(), cell_data)];
+            found.extend(read_merged_cells(&cell));
+            for (cell_offset, cell_data) in found {
+                let offset = (cell_offset - HBIN_START_OFFSET) as u32;
+                match cell_data {
+                    CellData::NodeKey(nk) => {
+                        keys.insert(offset, nk);
+                    },
+                    CellData::ValueKey(vk) => {
+                        values.insert(offset, vk);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        DeletedCells {
+            keys: keys,
+            values: values
+        }
+    }
+
+    /// Set the format version of the hive on the recovered keys and values.
+    pub fn set_version(&mut self, version: HiveVersion) {
+        for nk in self.keys.values_mut() {
+            nk.set_version(version);
+        }
+        for vk in self.values.values_mut() {
+            vk.set_version(version);
+        }
+    }
+
+    /// Turn the deleted keys and values into deleted records.
+    ///
+    /// Values are matched to a deleted key through the key's value list. A deleted key
+    /// without any recovered value becomes a record of its own, and values that no deleted
+    /// key claims are returned with an unknown path.
+    pub fn into_records<Rs: Read+Seek>(self, reader: &mut Rs) -> Vec<DeletedRecord> {
+        let mut values = self.values;
+        let mut records = Vec::new();
+
+        for (offset, nk) in self.keys.iter() {
+            let path = get_key_path(reader, *offset, nk);
+            let mut value_count = 0;
+
+            for value_offset in read_value_offsets(reader, nk) {
+                let mut vk = match values.remove(&value_offset) {
+                    Some(vk) => vk,
+                    None => continue
+                };
+
+                match vk.read_value(reader) {
+                    Err(error) => debug!("Unable to read data of deleted value at {}: {}",value_offset,error),
+                    _ => {}
+                }
+
+                records.push(
+                    DeletedRecord::new(&path, nk, vk)
+                );
+                value_count += 1;
+            }
+
+            if value_count == 0 {
+                records.push(
+                    DeletedRecord::from_key(&path, nk)
+                );
+            }
+        }
+
+        for (value_offset, mut vk) in values {
+            match vk.read_value(reader) {
+                Err(error) => debug!("Unable to read data of deleted value at {}: {}",value_offset,error),
+                _ => {}
+            }
+
+            records.push(
+                DeletedRecord::from_orphan_value(vk)
+            );
+        }
+
+        records
+    }
+}
+
+/// Windows merges adjacent free cells, so more deleted keys and values can follow the
+/// first one inside a free cell. Look for further nk and vk cells at 8 byte aligned
+/// offsets that have a plausible size, and return them with their absolute offsets.
+fn read_merged_cells(cell: &Cell) -> Vec<(u64,CellData)> {
+    let mut found = Vec::new();
+
+    // The index in the cell data of a size field, which is 4 bytes past an aligned offset
+    let mut start = 4;
+    while start + 8 <= cell.data.len() {
+        let size = LittleEndian::read_i32(&cell.data[start..start + 4]);
+        let signature = &cell.data[start + 4..start + 6];
+        let plausible = size >= 8 && size % 8 == 0 && start + size as usize <= cell.data.len();
+        if plausible && (signature == b"nk" || signature == b"vk") {
+            let offset = cell.get_offset() + 4 + start as u64;
+            let merged = Cell::new(&mut &cell.data[start..], offset)
+                .and_then(|merged| merged.get_data());
+            match merged {
+                Ok(cell_data) => {
+                    found.push((offset, cell_data));
+                    start += size as usize;
+                    continue;
+                },
+                Err(error) => debug!("Unable to read merged cell at {}: {}",offset,error)
+            }
+        }
+        start += 8;
+    }
+
+    found
+}
+
+/// Read what is left of a deleted key's value list. The list cell may have been
+/// reused, so only as many offsets as the cell holds are returned.
+fn read_value_offsets<Rs: Read+Seek>(reader: &mut Rs, nk: &NodeKey) -> Vec<u32> {
+    let mut value_offsets = Vec::new();
+    if nk.get_num_values() == 0 || nk.get_value_list_offset() == 4294967295 {
+        return value_offsets;
+    }
+
+    let cell = match Cell::at_offset(reader, nk.get_value_list_offset() as u64 + HBIN_START_OFFSET) {
+        Ok(cell) => cell,
+        Err(error) => {
+            debug!("Unable to read value list of deleted key {}: {}",nk.key_name(),error);
+            return value_offsets;
+        }
+    };
+
+    let count = ::std::cmp::min(nk.get_num_values() as usize, cell.data.len() / 4);
+    for i in 0..count {
+        value_offsets.push(
+            LittleEndian::read_u32(&cell.data[i*4..i*4+4])
+        );
+    }
+
+    value_offsets
+}
+
+/// Build a best effort path for a key by following parent offsets up to the root key.
+/// If the chain breaks or loops, the path starts with the unknown path segment.
+pub fn get_key_path<Rs: Read+Seek>(reader: &mut Rs, offset: u32, nk: &NodeKey) -> String {
+    let mut names = vec![nk.key_name().clone()];
+    let mut visited = HashSet::new();
+    visited.insert(offset);
+
+    let mut found_root = nk.is_root();
+    let mut parent_offset = nk.get_parent_key_offset();
+    while !found_root {
+        if !visited.insert(parent_offset) {
+            break;
+        }
+
+        let parent = match Cell::at_offset(reader, parent_offset as u64 + HBIN_START_OFFSET) {
+            Ok(cell) => match cell.get_data() {
+                Ok(CellData::NodeKey(parent)) => parent,
+                _ => break
+            },
+            Err(_) => break
+        };
+
+        names.push(parent.key_name().clone());
+        found_root = parent.is_root();
+        parent_offset = parent.get_parent_key_offset();
+    }
+
+    if !found_root {
+        names.push(UNKNOWN_PATH.to_string());
+    }
+
+    names.reverse();
+    format!("\\{}",names.join("\\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hive::Hive;
+    use std::io::Cursor;
+    use std::fs::File;
+
+    #[test]
+    fn deleted_merged_cells() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        // A deleted key at 1961840 follows a 232 byte cell
+        let nk_offset = 1961840 - 4096;
+        let nk = NodeKey::at_offset(&mut Cursor::new(&buffer), nk_offset).unwrap();
+        let path = get_key_path(&mut Cursor::new(&buffer), nk_offset, &nk);
+        let records = Hive::from_source(Cursor::new(buffer.clone())).unwrap().get_deleted_records();
+        assert!(records.iter().any(|record| record.fullpath == path));
+
+        // Free the cell before it and merge the two
+        LittleEndian::write_i32(&mut buffer[1961608..1961612], 232 + 144);
+        let records = Hive::from_source(Cursor::new(buffer)).unwrap().get_deleted_records();
+        assert!(records.iter().any(|record| record.fullpath == path));
+    }
+}
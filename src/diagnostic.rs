@@ -0,0 +1,22 @@
+use errors::{RegError,ErrorKind};
+
+/// A structure that could not be read during a lenient walk and was skipped.
+#[derive(Serialize, Debug)]
+pub struct Diagnostic {
+    /// Absolute offset of the cell that could not be read
+    pub offset: u64,
+    /// Path of the key the cell belongs to
+    pub path: String,
+    pub kind: ErrorKind,
+    pub message: String
+}
+impl Diagnostic {
+    pub fn new(offset: u64, path: &str, error: &RegError) -> Diagnostic {
+        Diagnostic {
+            offset: offset,
+            path: path.to_string(),
+            kind: error.kind,
+            message: error.message.clone()
+        }
+    }
+}
@@ -4,17 +4,23 @@ use std::io;
 use std::fmt;
 use std::fmt::Display;
 
-#[derive(Debug)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
 pub enum ErrorKind {
     IoError,
     AsciiError,
     Utf16Error,
     FromUtf8Error,
     ValidationError,
-    SecDescParseError
+    SecDescParseError,
+    KeyNotFound,
+    UnexpectedCellType,
+    OutOfBounds,
+    SizeMismatch,
+    VolatileOffset,
+    VersionMismatch
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RegError {
     pub message: String,
     pub kind: ErrorKind,
@@ -48,6 +54,65 @@ impl RegError {
             trace: backtrace!()
         }
     }
+
+    #[allow(dead_code)]
+    pub fn key_not_found(err: String)->Self{
+        RegError {
+            message: format!("{}",err),
+            kind: ErrorKind::KeyNotFound,
+            trace: backtrace!()
+        }
+    }
+
+    /// A cell was found where a different type of cell was expected.
+    #[allow(dead_code)]
+    pub fn unexpected_cell_type(offset: u64, expected: &str, found: &str)->Self{
+        RegError {
+            message: format!("Expected {} cell at offset {}, found {}.",expected,offset,found),
+            kind: ErrorKind::UnexpectedCellType,
+            trace: backtrace!()
+        }
+    }
+
+    /// An offset or count points outside of the buffer or hive it refers to.
+    #[allow(dead_code)]
+    pub fn out_of_bounds(err: String)->Self{
+        RegError {
+            message: format!("{}",err),
+            kind: ErrorKind::OutOfBounds,
+            trace: backtrace!()
+        }
+    }
+
+    /// A structure is not valid for the format version of the hive.
+    #[allow(dead_code)]
+    pub fn version_mismatch(err: String)->Self{
+        RegError {
+            message: format!("{}",err),
+            kind: ErrorKind::VersionMismatch,
+            trace: backtrace!()
+        }
+    }
+
+    /// An offset points into volatile storage, which a hive file does not have.
+    #[allow(dead_code)]
+    pub fn volatile_offset(offset: u32)->Self{
+        RegError {
+            message: format!("Offset {:#x} is in volatile storage, which is not part of the hive file.",offset),
+            kind: ErrorKind::VolatileOffset,
+            trace: backtrace!()
+        }
+    }
+
+    /// A size field disagrees with the amount of data present.
+    #[allow(dead_code)]
+    pub fn size_mismatch(err: String)->Self{
+        RegError {
+            message: format!("{}",err),
+            kind: ErrorKind::SizeMismatch,
+            trace: backtrace!()
+        }
+    }
 }
 impl From<FromUtf8Error> for RegError {
     fn from(err: FromUtf8Error) -> Self {
@@ -79,7 +144,10 @@ impl From<SecDescError> for RegError {
 impl Display for RegError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.kind {
-            ErrorKind::ValidationError => {
+            ErrorKind::ValidationError | ErrorKind::KeyNotFound |
+            ErrorKind::UnexpectedCellType | ErrorKind::OutOfBounds |
+            ErrorKind::SizeMismatch | ErrorKind::VolatileOffset |
+            ErrorKind::VersionMismatch => {
                 write!(
                     f,
                     "{:?}: {}",
@@ -0,0 +1,240 @@
+use rwinstructs::timestamp::{WinTimestamp};
+use byteorder::{ByteOrder,LittleEndian};
+use utils;
+use errors::RegError;
+use hive::HBIN_START_OFFSET;
+use std::io::Read;
+use std::io::{Seek,SeekFrom};
+use std::cell::RefCell;
+
+pub const HBIN_SIGNATURE: u32 = 1852400232;
+pub const HBIN_HEADER_SIZE: usize = 32;
+pub const HBIN_ALIGNMENT: u32 = 4096;
+
+// hbin
+#[derive(Serialize, Debug)]
+pub struct HiveBin {
+    #[serde(skip_serializing)]
+    _offset: u64,
+    signature: u32,
+    offset: u32, // relative to the start of the hive bins data
+    size: u32,
+    reserved1: u64,
+    timestamp: WinTimestamp, // only set in the first hive bin
+    spare: u32
+}
+impl HiveBin {
+    pub fn new(buffer: &[u8], offset: u64) -> Result<HiveBin,RegError> {
+        utils::check_len(buffer, HBIN_HEADER_SIZE, "Hive bin header", offset)?;
+
+        let signature = LittleEndian::read_u32(&buffer[0..4]);
+        let relative_offset = LittleEndian::read_u32(&buffer[4..8]);
+        let size = LittleEndian::read_u32(&buffer[8..12]);
+        let reserved1 = LittleEndian::read_u64(&buffer[12..20]);
+        let timestamp = WinTimestamp(
+            LittleEndian::read_u64(&buffer[20..28])
+        );
+        let spare = LittleEndian::read_u32(&buffer[28..32]);
+
+        Ok(
+            HiveBin {
+                _offset: offset,
+                signature: signature,
+                offset: relative_offset,
+                size: size,
+                reserved1: reserved1,
+                timestamp: timestamp,
+                spare: spare
+            }
+        )
+    }
+
+    /// The absolute offset of this hive bin in the hive file.
+    pub fn get_offset(&self)->u64{
+        self._offset
+    }
+
+    /// The offset stored in the hive bin header (relative to the first hive bin).
+    pub fn get_relative_offset(&self)->u32{
+        self.offset
+    }
+
+    pub fn get_size(&self)->u32{
+        self.size
+    }
+
+    pub fn get_timestamp(&self)->&WinTimestamp{
+        &self.timestamp
+    }
+
+    pub fn get_spare(&self)->u32{
+        self.spare
+    }
+
+    pub fn has_valid_signature(&self)->bool {
+        self.signature == HBIN_SIGNATURE
+    }
+
+    /// The size of a hive bin must be a non zero multiple of 4096.
+    pub fn has_valid_size(&self)->bool {
+        self.size != 0 && self.size % HBIN_ALIGNMENT == 0
+    }
+
+    /// The relative offset stored in the header must match where the bin was read from.
+    pub fn has_valid_offset(&self)->bool {
+        self.offset as u64 + HBIN_START_OFFSET == self._offset
+    }
+
+    pub fn is_valid(&self)->bool {
+        self.has_valid_signature() && self.has_valid_size()
+    }
+}
+
+/// Iterates the hive bin headers between the base block and the end of the hive bins data.
+///
+/// A bin with a bad signature or size is still returned so that it can be reported,
+/// after which the iterator steps to the next 4096 byte boundary.
+pub struct HiveBinIterator<'a, Rs: 'a> {
+    source: &'a RefCell<Rs>,
+    next_offset: u64,
+    end_offset: u64
+}
+impl <'a, Rs: Read+Seek> HiveBinIterator<'a, Rs> {
+    /// Iterate the hive bins of a source. The source is only borrowed while a hive bin
+    /// is read.
+    pub fn new(source: &'a RefCell<Rs>, hive_bins_data_size: u32) -> HiveBinIterator<'a, Rs> {
+        HiveBinIterator {
+            source: source,
+            next_offset: HBIN_START_OFFSET,
+            end_offset: HBIN_START_OFFSET + hive_bins_data_size as u64
+        }
+    }
+
+    fn read_hive_bin(&mut self, offset: u64)->Result<HiveBin,RegError> {
+        let mut source = self.source.borrow_mut();
+        source.seek(
+            SeekFrom::Start(offset)
+        )?;
+
+        let mut buffer = [0; HBIN_HEADER_SIZE];
+        source.read_exact(&mut buffer)?;
+
+        HiveBin::new(&buffer, offset)
+    }
+
+    /// Read the cell area of a hive bin (everything after its header).
+    pub fn read_cell_buffer(&mut self, hive_bin: &HiveBin)->Result<Vec<u8>,RegError> {
+        let start = hive_bin.get_offset() + HBIN_HEADER_SIZE as u64;
+        let end = hive_bin.get_offset() + hive_bin.get_size() as u64;
+        if !hive_bin.has_valid_size() || end > self.end_offset {
+            return Err(
+                RegError::validation_error(
+                    format!("Hive bin at offset {} with size {} exceeds the hive bins data.",
+                        hive_bin.get_offset(), hive_bin.get_size()
+                    )
+                )
+            );
+        }
+
+        let mut source = self.source.borrow_mut();
+        source.seek(
+            SeekFrom::Start(start)
+        )?;
+
+        // Read through take() so a bogus size cannot allocate more than the source holds
+        let mut buffer = Vec::new();
+        source.by_ref().take(end - start).read_to_end(&mut buffer)?;
+        if (buffer.len() as u64) < end - start {
+            return Err(
+                RegError::validation_error(
+                    format!("Hive bin at offset {} with size {} extends past the end of the source.",
+                        hive_bin.get_offset(), hive_bin.get_size()
+                    )
+                )
+            );
+        }
+
+        Ok(buffer)
+    }
+}
+impl <'a, Rs: Read+Seek> Iterator for HiveBinIterator<'a, Rs> {
+    type Item = Result<HiveBin,RegError>;
+
+    fn next(&mut self) -> Option<Result<HiveBin,RegError>> {
+        if self.next_offset >= self.end_offset {
+            return None;
+        }
+
+        let offset = self.next_offset;
+        match self.read_hive_bin(offset) {
+            Ok(hive_bin) => {
+                if hive_bin.is_valid() {
+                    self.next_offset += hive_bin.get_size() as u64;
+                } else {
+                    self.next_offset += HBIN_ALIGNMENT as u64;
+                }
+                Some(Ok(hive_bin))
+            },
+            Err(error) => {
+                // We cannot read past this point
+                self.next_offset = self.end_offset;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read,Cursor};
+    use std::fs::File;
+
+    #[test]
+    fn hivebin() {
+        let mut file = File::open(".testdata/NTUSER_4096_32_HIVEBIN_HEADER.DAT").unwrap();
+        let mut buffer = Vec::new();
+
+        match file.read_to_end(&mut buffer){
+            Err(error)=>panic!("{:?}",error),
+            _ => {}
+        }
+
+        let hbin = match HiveBin::new(&buffer,4096){
+            Ok(hbin)=>hbin,
+            Err(error)=>panic!("{:?}",error)
+        };
+
+        assert_eq!(hbin.signature, 1852400232);
+        assert_eq!(hbin.offset, 0);
+        assert_eq!(hbin.size, 4096);
+        assert_eq!(hbin.reserved1, 0);
+        assert_eq!(hbin.timestamp.0, 130216723045201708);
+        assert_eq!(hbin.spare, 0);
+
+        assert_eq!(hbin.has_valid_signature(), true);
+        assert_eq!(hbin.has_valid_size(), true);
+        assert_eq!(hbin.has_valid_offset(), true);
+    }
+
+    #[test]
+    fn hivebin_short_buffer() {
+        assert!(HiveBin::new(&[0x68,0x62,0x69,0x6E],4096).is_err());
+    }
+
+    #[test]
+    fn hivebin_size_past_source() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = vec![0; 8192];
+        file.read_exact(&mut buffer).unwrap();
+
+        // A valid looking size far past the end of the source and hive bins data
+        LittleEndian::write_u32(&mut buffer[4096 + 8..4096 + 12], 0xFFFF0000);
+        let source = RefCell::new(Cursor::new(buffer));
+        let mut hive_bins = HiveBinIterator::new(&source, 0xFFFFF000);
+
+        let hive_bin = hive_bins.next().unwrap().unwrap();
+        assert!(hive_bin.is_valid());
+        assert!(hive_bins.read_cell_buffer(&hive_bin).is_err());
+    }
+}
@@ -1,10 +1,23 @@
 use byteorder::{ReadBytesExt,LittleEndian};
-use baseblock::BaseBlock;
-use record::Record;
+use baseblock::{BaseBlock,HiveVersion};
+use hbin::HiveBinIterator;
+use record::{Record,KeyRecord,DeletedRecord};
 use cell::Cell;
 use cell::CellData;
+use cell::CellIterator;
 use nk::NodeKey;
+use sk::SecurityKeyList;
+use deleted;
+use deleted::DeletedCells;
+use slack::{Slack,SlackCells};
 use errors::RegError;
+use diagnostic::Diagnostic;
+use verify::VerifyReport;
+use key::Key;
+use volatile;
+use volatile::VolatileSource;
+use std::cell::{RefCell,RefMut};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 use std::io::Seek;
@@ -24,10 +37,12 @@ pub fn has_hive_signature(filename: &str)->Result<bool,RegError>{
 #[derive(Serialize,Debug)]
 pub struct Hive<Rs> {
     #[serde(skip_serializing)]
-    source: Rs,
+    source: RefCell<Rs>,
     baseblock: Box<BaseBlock>,
-    node_stack: Vec<Box<NodeKey>>,
-    current_path: String
+    #[serde(skip_serializing)]
+    version: HiveVersion,
+    #[serde(skip_serializing)]
+    volatile: bool
 }
 impl <Rs: Read + Seek> Hive<Rs> {
     pub fn from_source(mut source: Rs) -> Result<Hive<Rs>,RegError>{
@@ -35,17 +50,48 @@ impl <Rs: Read + Seek> Hive<Rs> {
         source.read_exact(&mut buffer_baseblock)?;
 
         let baseblock = BaseBlock::new(&buffer_baseblock,0)?;
+        let version = match baseblock.get_version().is_supported() {
+            true => baseblock.get_version(),
+            false => {
+                warn!(
+                    "Hive format version {} is not a known version; reading it as version {}.",
+                    baseblock.get_version(), HiveVersion::latest()
+                );
+                HiveVersion::latest()
+            }
+        };
+        if baseblock.is_dirty() {
+            warn!(
+                "Hive is dirty (sequence numbers {} and {}); use transaction_log::recover_hive to apply its logs.",
+                baseblock.primary_seq_num(), baseblock.secondary_seq_num()
+            );
+        }
 
         Ok(
             Hive {
-                source: source,
+                source: RefCell::new(source),
                 baseblock: Box::new(baseblock),
-                node_stack: Vec::new(),
-                current_path: String::from("")
+                version: version,
+                volatile: false
             }
         )
     }
 
+    /// Check if volatile cells can be read, i.e. the hive was opened with from_memory.
+    pub fn has_volatile_storage(&self)->bool{
+        self.volatile
+    }
+
+    pub fn get_baseblock(&self)->&BaseBlock{
+        &self.baseblock
+    }
+
+    /// The format version structures are read for: the version of the base block, or the
+    /// latest version when the base block has an unknown one.
+    pub fn get_version(&self)->HiveVersion{
+        self.version
+    }
+
     /// Get the root node.
     ///
     /// # Examples
@@ -57,9 +103,9 @@ impl <Rs: Read + Seek> Hive<Rs> {
     /// use rwinreg::hive::Hive;
     ///
     /// # fn test_get_root_node() {
-    /// let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
     ///
-    /// let mut hive = match Hive::from_source(file){
+    /// let hive = match Hive::from_source(file){
     ///     Ok(h) => h,
     ///     Err(e) => panic!(e)
     /// };
@@ -71,73 +117,1088 @@ impl <Rs: Read + Seek> Hive<Rs> {
     /// assert_eq!(node.key_name(), "CsiTool-CreateHive-{00000000-0000-0000-0000-000000000000}");
     /// # }
     /// ```
-    pub fn get_root_node(&mut self)->Result<NodeKey, RegError>{
+    pub fn get_root_node(&self)->Result<NodeKey, RegError>{
         let offset = self.baseblock.root_cell_offset() as u64 + HBIN_START_OFFSET;
-        match Cell::at_offset(&mut self.source, offset)?.get_data()? {
-            CellData::NodeKey(nk) => Ok(nk),
+        match Cell::at_offset(&mut *self.source.borrow_mut(), offset)?.get_data()? {
+            CellData::NodeKey(mut nk) => {
+                nk.set_version(self.version);
+                Ok(nk)
+            },
             other => {
-                panic!("Root node is not NodeKey: {:?}",other)
+                Err(
+                    RegError::unexpected_cell_type(offset, "nk", other.get_type_name())
+                )
             }
         }
     }
 
-    pub fn get_next_value(&mut self)->Result<Option<Record>, RegError>{
-        if self.node_stack.len() == 0 {
-            let node = self.get_root_node()?;
+    /// Open a key by its path relative to the root key. Path components are separated
+    /// by backslashes and matched case insensitively.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_open_key() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let node = match hive.open_key("software\\MICROSOFT\\Windows\\CurrentVersion\\Run"){
+    ///     Ok(n) => n,
+    ///     Err(e) => panic!(e)
+    /// };
+    /// assert_eq!(node.key_name(), "Run");
+    ///
+    /// assert!(hive.open_key("Software\\DoesNotExist").is_err());
+    /// # }
+    /// # test_open_key();
+    /// ```
+    pub fn open_key(&self, path: &str)->Result<NodeKey, RegError>{
+        let (_, mut node) = self.find_key(path)?;
+        let mut source = self.source.borrow_mut();
+
+        node.set_security_key(&mut *source)?;
+        node.read_class_name(&mut *source)?;
+        Ok(node)
+    }
+
+    /// Find a key by its path relative to the root key. Returns the key along with its
+    /// full path, spelled the way the key names are stored.
+    fn find_key(&self, path: &str)->Result<(String, NodeKey), RegError>{
+        let mut node = self.get_root_node()?;
+        let mut full_path = format!("\\{}",node.key_name());
+        let mut source = self.source.borrow_mut();
+
+        for name in path.split('\\').filter(|name| !name.is_empty()) {
+            node = match node.find_sub_key(&mut *source, name)? {
+                Some(sub_key) => sub_key,
+                None => {
+                    return Err(
+                        RegError::key_not_found(
+                            format!("Key {} not found under {}: {}",name,node.key_name(),path)
+                        )
+                    );
+                }
+            };
+            full_path.push_str(&format!("\\{}",node.key_name()));
+        }
+
+        Ok((full_path, node))
+    }
+
+    /// Get the root key as a Key handle.
+    pub fn get_root_key(&self)->Result<Key<Rs>, RegError>{
+        let node = self.get_root_node()?;
+        let path = format!("\\{}",node.key_name());
+        Ok(Key::new(self, node, path))
+    }
+
+    /// Get a key as a Key handle by its path relative to the root key. Path components
+    /// are separated by backslashes and matched case insensitively.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_get_key() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let run = hive.get_key("software\\microsoft\\windows\\currentversion\\run").unwrap();
+    /// assert_eq!(run.get_name(), "Run");
+    /// assert!(run.get_path().ends_with("\\Software\\Microsoft\\Windows\\CurrentVersion\\Run"));
+    ///
+    /// let current_version = run.parent().unwrap().unwrap();
+    /// assert_eq!(current_version.get_name(), "CurrentVersion");
+    /// assert!(current_version.subkeys().unwrap().iter().any(|key| key.get_name() == "Run"));
+    ///
+    /// let imemip = hive.get_key("Software\\Microsoft\\IMEMIP").unwrap();
+    /// assert_eq!(imemip.get_class_name().unwrap(), Some("Software\\Microsoft\\IMEMIP\\0x0409".to_string()));
+    /// # }
+    /// # test_get_key();
+    /// ```
+    pub fn get_key(&self, path: &str)->Result<Key<Rs>, RegError>{
+        let (full_path, node) = self.find_key(path)?;
+        Ok(Key::new(self, node, full_path))
+    }
+
+    /// Get the key whose cell is at an offset relative to the first hive bin, such as a
+    /// key carved from unallocated space or found through a hash. Its path is rebuilt by
+    /// climbing parent offsets; a chain that breaks or loops starts with `[UNKNOWN]`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_key_at_offset() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let run = hive.get_key("Software\\Microsoft\\Windows\\CurrentVersion\\Run").unwrap();
+    /// let key = hive.key_at_offset(run.get_offset()).unwrap();
+    /// assert_eq!(key.get_path(), run.get_path());
+    ///
+    /// assert!(hive.key_at_offset(4680 - 4096).is_err());
+    /// # }
+    /// # test_key_at_offset();
+    /// ```
+    pub fn key_at_offset(&self, offset: u32)->Result<Key<Rs>, RegError>{
+        let (node, path) = {
+            let mut source = self.source.borrow_mut();
+            let mut node = NodeKey::at_offset(&mut *source, offset)?;
+            node.set_version(self.version);
+            let path = deleted::get_key_path(&mut *source, offset, &node);
+            (node, path)
+        };
+        Ok(Key::new(self, node, path))
+    }
 
-            self.current_path.push_str(&format!("\\{}",node.key_name()));
+    /// Read the node key at an offset relative to the first hive bin, for the format
+    /// version of the hive.
+    pub(crate) fn read_node_key(&self, offset: u32)->Result<NodeKey, RegError>{
+        let mut nk = NodeKey::at_offset(&mut *self.source.borrow_mut(), offset)?;
+        nk.set_version(self.version);
+        Ok(nk)
+    }
+
+    /// Borrow the source of the hive, for handles that read from it.
+    pub(crate) fn borrow_source(&self)->RefMut<Rs>{
+        self.source.borrow_mut()
+    }
 
-            self.node_stack.push(
-                Box::new(node)
+    /// Iterate the hive bins of the hive.
+    ///
+    /// # Examples
+    ///
+    /// Check that the hive bins cover the hive bins data size of the base block.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_hive_bins() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let mut total_size = 0;
+    /// for hive_bin in hive.hive_bins() {
+    ///     let hive_bin = hive_bin.unwrap();
+    ///     assert!(hive_bin.is_valid());
+    ///     total_size += hive_bin.get_size();
+    /// }
+    /// assert_eq!(total_size, 3563520);
+    /// # }
+    /// # test_hive_bins();
+    /// ```
+    pub fn hive_bins(&self)->HiveBinIterator<Rs>{
+        HiveBinIterator::new(
+            &self.source,
+            self.baseblock.hive_bins_data_size()
+        )
+    }
+
+    /// Iterate every cell in the hive, allocated or not, without following the key tree.
+    ///
+    /// # Examples
+    ///
+    /// Count the unallocated node keys of a hive.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::cell::CellData;
+    ///
+    /// # fn test_cells() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let mut free_nk_count = 0;
+    /// for result in hive.cells() {
+    ///     let (cell, cell_data) = result.unwrap();
+    ///     match cell_data {
+    ///         CellData::NodeKey(_) if !cell.is_allocated() => free_nk_count += 1,
+    ///         _ => {}
+    ///     }
+    /// }
+    /// assert_eq!(free_nk_count, 11);
+    /// # }
+    /// # test_cells();
+    /// ```
+    pub fn cells(&self)->CellIterator<Rs>{
+        CellIterator::new(
+            self.hive_bins()
+        )
+    }
+
+    /// Recover deleted keys and values from the unallocated cells of the hive.
+    ///
+    /// Returned records carry a best effort path built by
+    /// following parent offsets. Values that no deleted key claims have an unknown path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_get_deleted_records() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let records = hive.get_deleted_records();
+    /// assert!(records.iter().all(|r| r.deleted));
+    /// assert!(records.iter().any(|r| r.fullpath.ends_with("\\Resiliency\\StartupItems")));
+    /// # }
+    /// # test_get_deleted_records();
+    /// ```
+    pub fn get_deleted_records(&self)->Vec<DeletedRecord>{
+        let mut deleted_cells = DeletedCells::from_cells(
+            self.cells()
+        );
+        deleted_cells.set_version(self.version);
+
+        deleted_cells.into_records(
+            &mut *self.source.borrow_mut()
+        )
+    }
+
+    /// Get the slack of every allocated key, value and value data cell.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::slack::SlackSource;
+    ///
+    /// # fn test_get_slack() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let slack = hive.get_slack();
+    /// let root_slack = slack.iter().find(|s| s.owner_offset == 4128).unwrap();
+    /// assert_eq!(root_slack.source, SlackSource::NodeKey);
+    /// assert_eq!(root_slack.data, vec![0x00,0x39,0x00,0x31,0x00,0x45,0x00]);
+    /// assert!(slack.iter().any(|s| s.source == SlackSource::ValueData));
+    /// # }
+    /// # test_get_slack();
+    /// ```
+    pub fn get_slack(&self)->Vec<Slack>{
+        let mut slack_cells = SlackCells::from_cells(
+            self.cells()
+        );
+        slack_cells.set_version(self.version);
+
+        slack_cells.into_slack(
+            &mut *self.source.borrow_mut()
+        )
+    }
+
+    /// Check the structure of the hive: the base block checksum and sequence numbers, the
+    /// hive bins and their cells, and the key tree. Every inconsistency found is collected
+    /// in the report rather than stopping the check.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::verify::IssueKind;
+    ///
+    /// # fn test_verify() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// // The test hive was copied from a live system and was never flushed
+    /// let report = hive.verify();
+    /// assert_eq!(report.count(IssueKind::DirtySequenceNumbers), 1);
+    /// assert_eq!(report.count(IssueKind::ChecksumMismatch), 0);
+    /// assert_eq!(report.count(IssueKind::ParentMismatch), 0);
+    /// assert_eq!(report.count(IssueKind::CellReferencedTwice), 0);
+    /// # }
+    /// # test_verify();
+    /// ```
+    pub fn verify(&self)->VerifyReport{
+        let mut report = VerifyReport::new();
+        report.check_baseblock(&self.baseblock);
+
+        report.check_hive_bins(
+            self.hive_bins()
+        );
+
+        let root_offset = self.baseblock.root_cell_offset();
+        report.check_keys(
+            &mut *self.source.borrow_mut(),
+            root_offset,
+            self.version
+        );
+
+        report
+    }
+
+    /// Iterate the keys of the hive depth first, starting with the root key. Each key
+    /// comes with its full path.
+    ///
+    /// # Examples
+    ///
+    /// Find the keys that have no values.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_keys() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let empty_keys: Vec<String> = hive.keys()
+    ///     .filter_map(|result| result.ok())
+    ///     .filter(|&(_, ref nk)| nk.get_num_values() == 0)
+    ///     .map(|(path, _)| path)
+    ///     .collect();
+    /// assert!(empty_keys.iter().any(|path| path.ends_with("\\Software\\Microsoft")));
+    ///
+    /// // Walks are independent of each other
+    /// assert_eq!(hive.keys().count(), hive.keys().count());
+    /// # }
+    /// # test_keys();
+    /// ```
+    pub fn keys(&self)->KeyIterator<Rs>{
+        KeyIterator {
+            hive: self,
+            stack: Vec::new(),
+            started: false,
+            pending_error: None,
+            lenient: false,
+            diagnostics: Vec::new(),
+            visited: HashSet::new()
+        }
+    }
+
+    /// Iterate the values of the hive as records, in the same order as the keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_records() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let run_records: Vec<_> = hive.records()
+    ///     .filter_map(|result| result.ok())
+    ///     .filter(|record| record.fullpath.contains("\\CurrentVersion\\Run\\"))
+    ///     .take(2)
+    ///     .collect();
+    /// assert!(run_records.len() > 0);
+    /// # }
+    /// # test_records();
+    /// ```
+    ///
+    /// A key whose security key cannot be read is returned as an error, and its values
+    /// still follow.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::{Read,Cursor};
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_records_bad_security_key() {
+    /// let mut buffer = Vec::new();
+    /// File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+    /// let record_count = Hive::from_source(Cursor::new(buffer.clone())).unwrap().records().count();
+    ///
+    /// // The signature of the security key shared by 84 keys
+    /// buffer[4096 + 672 + 4] = 0;
+    /// let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+    ///
+    /// let results: Vec<_> = hive.records().collect();
+    /// assert_eq!(results.iter().filter(|result| result.is_err()).count(), 84);
+    /// assert_eq!(results.iter().filter(|result| result.is_ok()).count(), record_count);
+    /// # }
+    /// # test_records_bad_security_key();
+    /// ```
+    pub fn records(&self)->RecordIterator<Rs>{
+        RecordIterator {
+            keys: self.keys(),
+            current_key: None,
+            pending_errors: Vec::new(),
+            security_ids: false
+        }
+    }
+
+    /// Iterate the keys of the hive as key records, including keys without values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_key_records() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let key_records: Vec<_> = hive.key_records()
+    ///     .map(|result| result.unwrap())
+    ///     .collect();
+    /// assert_eq!(key_records.len(), hive.keys().count());
+    ///
+    /// let root = &key_records[0];
+    /// assert_eq!(root.fullpath, "\\CsiTool-CreateHive-{00000000-0000-0000-0000-000000000000}");
+    /// assert_eq!(root.num_sub_keys, 13);
+    /// assert!(root.security.is_some());
+    /// assert!(key_records.iter().any(|r| r.num_values == 0));
+    ///
+    /// let imemip = key_records.iter().find(|r| r.fullpath.ends_with("\\Microsoft\\IMEMIP")).unwrap();
+    /// assert_eq!(imemip.class_name, Some("Software\\Microsoft\\IMEMIP\\0x0409".to_string()));
+    /// # }
+    /// # test_key_records();
+    /// ```
+    ///
+    /// A class name that cannot be read leaves the key record without it and is noted as
+    /// a diagnostic.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::{Read,Cursor};
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::errors::ErrorKind;
+    ///
+    /// # fn test_key_records_bad_class_name() {
+    /// let mut buffer = Vec::new();
+    /// File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+    /// let class_name_cell = {
+    ///     let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+    ///     hive.open_key("Software\\Microsoft\\IMEMIP").unwrap().get_class_name_offset() as usize + 4096
+    /// };
+    /// // An invalid cell size
+    /// buffer[class_name_cell..class_name_cell + 4].copy_from_slice(&[0, 0, 0, 0]);
+    ///
+    /// let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+    /// let mut key_records = hive.key_records();
+    /// let imemip = key_records.by_ref()
+    ///     .map(|result| result.unwrap())
+    ///     .find(|r| r.fullpath.ends_with("\\Microsoft\\IMEMIP"))
+    ///     .unwrap();
+    /// assert_eq!(imemip.class_name, None);
+    ///
+    /// let diagnostic = key_records.get_diagnostics().iter()
+    ///     .find(|d| d.kind != ErrorKind::VolatileOffset)
+    ///     .unwrap();
+    /// assert!(diagnostic.path.ends_with("\\Microsoft\\IMEMIP"));
+    /// # }
+    /// # test_key_records_bad_class_name();
+    /// ```
+    pub fn key_records(&self)->KeyRecordIterator<Rs>{
+        KeyRecordIterator {
+            keys: self.keys(),
+            pending_error: None,
+            security_ids: false
+        }
+    }
+
+    /// Walk the list of security keys from the one of the root key, caching each by its
+    /// offset, and count the keys that point at each. Records from iterators set to
+    /// `security_ids()` refer to the descriptors by these offsets.
+    ///
+    /// A broken list is an error. Keys are walked leniently; those pointing at a security
+    /// key that is not on the list are listed by `unlisted_keys`, and reference counts
+    /// that disagree with the keys counted by `reference_count_mismatches`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_security_keys() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    ///
+    /// let hive = match Hive::from_source(file){
+    ///     Ok(h) => h,
+    ///     Err(e) => panic!(e)
+    /// };
+    ///
+    /// let security_keys = hive.security_keys().unwrap();
+    /// assert_eq!(security_keys.len(), 116);
+    /// assert!(security_keys.reference_count_mismatches().is_empty());
+    /// assert!(security_keys.unlisted_keys().is_empty());
+    ///
+    /// // Records refer to the cached descriptors
+    /// let record = hive.records().security_ids().next().unwrap().unwrap();
+    /// assert!(record.security.is_none());
+    /// let descriptor = security_keys.get_descriptor(record.security_id.unwrap());
+    /// assert!(descriptor.is_some());
+    ///
+    /// let root = hive.key_records().security_ids().next().unwrap().unwrap();
+    /// assert_eq!(root.security_id, Some(security_keys.get_offsets()[0]));
+    /// # }
+    /// # test_security_keys();
+    /// ```
+    pub fn security_keys(&self)->Result<SecurityKeyList, RegError>{
+        let root = self.get_root_node()?;
+        let mut security_keys = SecurityKeyList::read(
+            &mut *self.source.borrow_mut(),
+            root.get_security_key_offset()
+        )?;
+
+        for result in self.keys().lenient() {
+            let (path, nk) = result?;
+            if let Some(offset) = nk.get_security_id() {
+                security_keys.count_key(&path, offset);
+            }
+        }
+
+        Ok(security_keys)
+    }
+}
+
+/// Walk over the keys of a hive, yielding a key record for each key.
+pub struct KeyRecordIterator<'a, Rs: 'a> {
+    keys: KeyIterator<'a, Rs>,
+    pending_error: Option<RegError>,
+    security_ids: bool
+}
+impl <'a, Rs: Read+Seek> KeyRecordIterator<'a, Rs> {
+    /// Skip what cannot be read and collect it as diagnostics instead of returning errors.
+    /// A key whose security key cannot be read is returned without it. A class name that
+    /// cannot be read is always collected as a diagnostic, not only in lenient mode.
+    pub fn lenient(mut self)->KeyRecordIterator<'a, Rs>{
+        self.keys = self.keys.lenient();
+        self
+    }
+
+    /// Give each key record the offset of its security key as `security_id` instead of
+    /// a copy of the descriptor. Security keys are then not read per key.
+    pub fn security_ids(mut self)->KeyRecordIterator<'a, Rs>{
+        self.security_ids = true;
+        self
+    }
+
+    pub fn get_diagnostics(&self)->&Vec<Diagnostic>{
+        self.keys.get_diagnostics()
+    }
+}
+impl <'a, Rs: Read+Seek> Iterator for KeyRecordIterator<'a, Rs> {
+    type Item = Result<KeyRecord,RegError>;
+
+    fn next(&mut self) -> Option<Result<KeyRecord,RegError>> {
+        if let Some(error) = self.pending_error.take() {
+            return Some(Err(error));
+        }
+
+        let (path, mut nk) = match self.keys.next() {
+            Some(Ok(entry)) => entry,
+            Some(Err(error)) => return Some(Err(error)),
+            None => return None
+        };
+
+        // A security key error is returned after the key record
+        if !self.security_ids {
+            let security_result = nk.set_security_key(&mut *self.keys.hive.source.borrow_mut());
+            if let Err(error) = security_result {
+                if let Some(Err(error)) = self.keys.report::<()>(nk.get_security_key_offset() as u64 + HBIN_START_OFFSET, &path, error) {
+                    self.pending_error = Some(error);
+                }
+            }
+        }
+
+        // The record is returned without a class name that cannot be read, in either mode
+        let class_name_result = nk.read_class_name(&mut *self.keys.hive.source.borrow_mut());
+        if let Err(error) = class_name_result {
+            let offset = nk.get_class_name_offset() as u64 + HBIN_START_OFFSET;
+            debug!("Class name of {} at offset {} could not be read: {}",path,offset,error);
+            self.keys.diagnostics.push(
+                Diagnostic::new(offset, &path, &error)
             );
         }
 
-        loop {
-            let index = self.node_stack.len() - 1;
-            match self.node_stack[index].get_next_value(&mut self.source)?{
-                Some(mut vk) => {
-                    vk.read_value(&mut self.source)?;
-                    let record = Record::new(
-                        &self.current_path,
-                        &self.node_stack[index],
-                        vk
-                    );
-                    return Ok(
-                        Some(record)
-                    );
-                },
-                None => {}
+        let mut key_record = KeyRecord::new(&path, &nk);
+        if self.security_ids {
+            key_record.security_id = nk.get_security_id();
+        }
+        Some(Ok(key_record))
+    }
+}
+
+impl <Rs: Read + Seek> Hive<VolatileSource<Rs>> {
+    /// Open a hive carved from memory from its stable storage (base block and hive bins)
+    /// and its volatile storage, laid out as described for VolatileSource. The key walks
+    /// and Key::volatile_subkeys then follow volatile subkey lists as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    ///
+    /// # fn test_from_memory() {
+    /// let stable = File::open(".testdata/NTUSER.DAT").unwrap();
+    /// // The volatile hive bins, as read from a memory image
+    /// let volatile = vec![0u8; 0x1000];
+    ///
+    /// let hive = Hive::from_memory(stable, volatile).unwrap();
+    /// assert!(hive.has_volatile_storage());
+    /// # }
+    /// # test_from_memory();
+    /// ```
+    pub fn from_memory(stable: Rs, volatile: Vec<u8>) -> Result<Hive<VolatileSource<Rs>>,RegError>{
+        let mut hive = Hive::from_source(
+            VolatileSource::new(stable, volatile)
+        )?;
+        hive.volatile = true;
+        Ok(hive)
+    }
+}
+
+/// A key that has been returned by the walk and whose subkeys are still being visited.
+struct KeyFrame {
+    path: String,
+    sub_key_offsets: Vec<u32>,
+    next_index: usize
+}
+
+/// Depth first walk over the keys of a hive. The walk state lives here rather than in
+/// the hive, so any number of walks can run over the same hive.
+///
+/// A subkey that cannot be read is returned as an error, or collected as a diagnostic
+/// in lenient mode, and the walk continues with its next sibling. Volatile subkeys are
+/// visited after the stable ones when the hive has volatile storage.
+pub struct KeyIterator<'a, Rs: 'a> {
+    hive: &'a Hive<Rs>,
+    stack: Vec<KeyFrame>,
+    started: bool,
+    pending_error: Option<RegError>,
+    lenient: bool,
+    diagnostics: Vec<Diagnostic>,
+    visited: HashSet<u32>
+}
+impl <'a, Rs: Read+Seek> KeyIterator<'a, Rs> {
+    /// Skip what cannot be read and collect it as diagnostics instead of returning errors.
+    ///
+    /// # Examples
+    ///
+    /// Point the first subkey of the root key back at the root key.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::{Read,Cursor};
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::errors::ErrorKind;
+    ///
+    /// # fn test_keys_lenient() {
+    /// let mut buffer = Vec::new();
+    /// File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+    ///
+    /// let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+    /// let list_offset = hive.get_root_node().unwrap().get_sub_key_list_offset() as usize;
+    /// let element = list_offset + 4096 + 4 + 4;
+    /// buffer[element..element + 4].copy_from_slice(&[32, 0, 0, 0]);
+    ///
+    /// // A key is walked once, so the loop ends the walk of that subkey
+    /// let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+    /// assert_eq!(hive.keys().filter(|result| result.is_err()).count(), 1);
+    ///
+    /// let mut keys = hive.keys().lenient();
+    /// assert!(keys.by_ref().all(|result| result.is_ok()));
+    /// let diagnostics: Vec<_> = keys.get_diagnostics().iter()
+    ///     .filter(|d| d.kind != ErrorKind::VolatileOffset)
+    ///     .collect();
+    /// assert_eq!(diagnostics.len(), 1);
+    /// assert_eq!(diagnostics[0].offset, 4096 + 32);
+    /// # }
+    /// # test_keys_lenient();
+    /// ```
+    pub fn lenient(mut self)->KeyIterator<'a, Rs>{
+        self.lenient = true;
+        self
+    }
+
+    /// What was skipped so far in lenient mode, and in either mode the volatile subkey
+    /// lists that could not be followed because the hive has no volatile storage.
+    pub fn get_diagnostics(&self)->&Vec<Diagnostic>{
+        &self.diagnostics
+    }
+
+    /// Hand an error back to the caller, or keep it as a diagnostic in lenient mode.
+    fn report<T>(&mut self, offset: u64, path: &str, error: RegError)->Option<Result<T,RegError>>{
+        if self.lenient {
+            debug!("Skipping {} at offset {}: {}",path,offset,error);
+            self.diagnostics.push(
+                Diagnostic::new(offset, path, &error)
+            );
+            None
+        } else {
+            Some(Err(error))
+        }
+    }
+
+    /// Remember a key so that its subkeys are visited next.
+    fn push_key(&mut self, path: &str, nk: &NodeKey) {
+        let mut sub_key_offsets = match nk.get_sub_key_offsets(&mut *self.hive.source.borrow_mut()) {
+            Ok(offsets) => offsets,
+            Err(error) => {
+                let offset = nk.get_sub_key_list_offset() as u64 + HBIN_START_OFFSET;
+                if let Some(Err(error)) = self.report::<()>(offset, path, error) {
+                    self.pending_error = Some(error);
+                }
+                Vec::new()
             }
+        };
 
-            match self.node_stack[index].get_next_key(&mut self.source)?{
-                Some(mut key) => {
-                    debug!("[{}] key: {}",index,key.key_name());
-                    key.set_security_key(&mut self.source)?;
-                    self.current_path.push_str(
-                        &format!("\\{}",key.key_name())
-                    );
-                    self.node_stack.push(
-                        Box::new(key)
-                    );
+        if nk.has_volatile_sub_keys() {
+            let list_offset = nk.get_volatile_sub_key_list_offset();
+            let offset = list_offset as u64 + HBIN_START_OFFSET;
+            if self.hive.volatile {
+                match nk.get_volatile_sub_key_offsets(&mut *self.hive.source.borrow_mut()) {
+                    Ok(offsets) => sub_key_offsets.extend(offsets),
+                    Err(error) => {
+                        if let Some(Err(error)) = self.report::<()>(offset, path, error) {
+                            if self.pending_error.is_none() {
+                                self.pending_error = Some(error);
+                            }
+                        }
+                    }
+                }
+            } else {
+                // Expected in a hive file, so noted in either mode rather than returned
+                debug!("Volatile subkey list of {} at offset {} is not in the hive file",path,list_offset);
+                self.diagnostics.push(
+                    Diagnostic::new(offset, path, &RegError::volatile_offset(list_offset))
+                );
+            }
+        }
+
+        self.stack.push(
+            KeyFrame {
+                path: path.to_string(),
+                sub_key_offsets: sub_key_offsets,
+                next_index: 0
+            }
+        );
+    }
+}
+impl <'a, Rs: Read+Seek> Iterator for KeyIterator<'a, Rs> {
+    type Item = Result<(String,NodeKey),RegError>;
+
+    fn next(&mut self) -> Option<Result<(String,NodeKey),RegError>> {
+        if let Some(error) = self.pending_error.take() {
+            return Some(Err(error));
+        }
+
+        if !self.started {
+            self.started = true;
+            return match self.hive.get_root_node() {
+                Ok(nk) => {
+                    self.visited.insert(self.hive.baseblock.root_cell_offset());
+                    let path = format!("\\{}",nk.key_name());
+                    self.push_key(&path, &nk);
+                    Some(Ok((path, nk)))
                 },
+                Err(error) => {
+                    let offset = self.hive.baseblock.root_cell_offset() as u64 + HBIN_START_OFFSET;
+                    self.report(offset, "", error)
+                }
+            };
+        }
+
+        loop {
+            let next_sub_key = match self.stack.last_mut() {
+                Some(frame) => {
+                    if frame.next_index < frame.sub_key_offsets.len() {
+                        frame.next_index += 1;
+                        Some((frame.path.clone(), frame.sub_key_offsets[frame.next_index - 1]))
+                    } else {
+                        None
+                    }
+                },
+                None => return None
+            };
+
+            let (path, offset) = match next_sub_key {
+                Some(next_sub_key) => next_sub_key,
                 None => {
-                    self.node_stack.pop();
-
-                    let new_path = match self.current_path.rfind("\\"){
-                        Some(index) => {
-                            self.current_path[0..index].to_string()
-                        },
-                        None => "".to_string()
-                    };
-                    self.current_path = new_path;
-
-                    if self.node_stack.len() == 0 {
-                        break;
+                    // All subkeys of this key have been visited
+                    self.stack.pop();
+                    continue;
+                }
+            };
+
+            if !self.visited.insert(offset) {
+                // A subkey list pointing back at an ancestor would walk forever
+                let error = RegError::validation_error(
+                    format!("Key at offset {} under {} was already walked.", offset, path)
+                );
+                match self.report(offset as u64 + HBIN_START_OFFSET, &path, error) {
+                    Some(item) => return Some(item),
+                    None => continue
+                }
+            }
+
+            let nk_result = match volatile::is_volatile(offset) && !self.hive.volatile {
+                true => Err(RegError::volatile_offset(offset)),
+                false => self.hive.read_node_key(offset)
+            };
+            let nk = match nk_result {
+                Ok(nk) => nk,
+                Err(error) => {
+                    match self.report(offset as u64 + HBIN_START_OFFSET, &path, error) {
+                        Some(item) => return Some(item),
+                        None => continue
                     }
                 }
             };
+
+            let path = format!("{}\\{}",path,nk.key_name());
+            debug!("[{}] key: {}",self.stack.len(),path);
+            self.push_key(&path, &nk);
+            return Some(Ok((path, nk)));
         }
+    }
+}
+
+/// A key whose values are being returned.
+struct ValueFrame {
+    path: String,
+    nk: NodeKey,
+    value_offsets: Vec<u32>,
+    next_index: usize
+}
 
-        Ok(None)
+/// Walk over the values of a hive, yielding a record for each value.
+///
+/// A value that cannot be read is returned as an error, or collected as a diagnostic in
+/// lenient mode, and the walk continues with the next value.
+pub struct RecordIterator<'a, Rs: 'a> {
+    keys: KeyIterator<'a, Rs>,
+    current_key: Option<ValueFrame>,
+    pending_errors: Vec<RegError>,
+    security_ids: bool
+}
+impl <'a, Rs: Read+Seek> RecordIterator<'a, Rs> {
+    /// Skip what cannot be read and collect it as diagnostics instead of returning errors.
+    /// A value whose data cannot be read is returned without its data.
+    ///
+    /// # Examples
+    ///
+    /// Damage a value key and a subkey list, then walk what is left of the hive.
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::{Read,Cursor};
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::errors::ErrorKind;
+    ///
+    /// # fn test_lenient() {
+    /// let mut buffer = Vec::new();
+    /// File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+    /// let record_count = Hive::from_source(Cursor::new(buffer.clone())).unwrap().records().count();
+    ///
+    /// // The User Agent value key
+    /// buffer[4684] = 0;
+    /// // The subkey list of \Software\Microsoft\IMEMIP
+    /// let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+    /// let imemip = hive.open_key("Software\\Microsoft\\IMEMIP").unwrap();
+    /// let list_offset = imemip.get_sub_key_list_offset() as usize + 4096 + 4;
+    /// buffer[list_offset] = 0;
+    ///
+    /// let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+    /// assert!(hive.records().any(|result| result.is_err()));
+    ///
+    /// let mut records = hive.records().lenient();
+    /// let lenient_count = records.by_ref().map(|result| result.unwrap()).count();
+    /// assert!(lenient_count > 0 && lenient_count < record_count);
+    ///
+    /// let diagnostics = records.get_diagnostics();
+    /// let diagnostics: Vec<_> = diagnostics.iter()
+    ///     .filter(|d| d.kind != ErrorKind::VolatileOffset)
+    ///     .collect();
+    /// assert_eq!(diagnostics.len(), 2);
+    /// assert!(diagnostics.iter().all(|d| d.kind == ErrorKind::UnexpectedCellType));
+    /// assert!(diagnostics.iter().any(|d| d.offset == 4680));
+    /// assert!(diagnostics.iter().any(|d| d.path.ends_with("\\Software\\Microsoft\\IMEMIP")));
+    /// # }
+    /// # test_lenient();
+    /// ```
+    pub fn lenient(mut self)->RecordIterator<'a, Rs>{
+        self.keys = self.keys.lenient();
+        self
+    }
+
+    /// Give each record the offset of its key's security key as `security_id` instead of
+    /// a copy of the descriptor. Security keys are then not read per key.
+    pub fn security_ids(mut self)->RecordIterator<'a, Rs>{
+        self.security_ids = true;
+        self
+    }
+
+    pub fn get_diagnostics(&self)->&Vec<Diagnostic>{
+        self.keys.get_diagnostics()
+    }
+
+    /// Read the value at an offset of the current key into a record.
+    fn read_record(&mut self, offset: u32)->Option<Result<Record,RegError>>{
+        let (path, vk_result) = match self.current_key {
+            Some(ref frame) => (
+                frame.path.clone(),
+                Cell::at_offset(&mut *self.keys.hive.source.borrow_mut(), offset as u64 + HBIN_START_OFFSET)
+                    .and_then(|cell| cell.get_data())
+            ),
+            None => return None
+        };
+
+        let mut vk = match vk_result {
+            Ok(CellData::ValueKey(mut vk)) => {
+                vk.set_version(self.keys.hive.version);
+                vk
+            },
+            Ok(other) => {
+                let offset = offset as u64 + HBIN_START_OFFSET;
+                let error = RegError::unexpected_cell_type(offset, "vk", other.get_type_name());
+                return self.keys.report(offset, &path, error);
+            },
+            Err(error) => return self.keys.report(offset as u64 + HBIN_START_OFFSET, &path, error)
+        };
+
+        let data_result = vk.read_value(&mut *self.keys.hive.source.borrow_mut());
+        if let Err(error) = data_result {
+            let data_offset = vk.get_data_offset() as u64 + HBIN_START_OFFSET;
+            if let Some(item) = self.keys.report(data_offset, &path, error) {
+                return Some(item);
+            }
+        }
+
+        match self.current_key {
+            Some(ref frame) => {
+                let mut value_record = Record::new(&frame.path, &frame.nk, vk);
+                if self.security_ids {
+                    value_record.security_id = frame.nk.get_security_id();
+                }
+                Some(Ok(value_record))
+            },
+            None => None
+        }
+    }
+}
+impl <'a, Rs: Read+Seek> Iterator for RecordIterator<'a, Rs> {
+    type Item = Result<Record,RegError>;
+
+    fn next(&mut self) -> Option<Result<Record,RegError>> {
+        loop {
+            if !self.pending_errors.is_empty() {
+                return Some(Err(self.pending_errors.remove(0)));
+            }
+
+            let next_value = match self.current_key {
+                Some(ref mut frame) => {
+                    if frame.next_index < frame.value_offsets.len() {
+                        frame.next_index += 1;
+                        Some(frame.value_offsets[frame.next_index - 1])
+                    } else {
+                        None
+                    }
+                },
+                None => None
+            };
+
+            if let Some(offset) = next_value {
+                match self.read_record(offset) {
+                    Some(item) => return Some(item),
+                    None => continue
+                }
+            }
+
+            let (path, mut nk) = match self.keys.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(error)) => return Some(Err(error)),
+                None => return None
+            };
+
+            // Errors are returned after the key is set up, so its values still follow
+            let mut pending_errors = Vec::new();
+            if !self.security_ids {
+                let security_result = nk.set_security_key(&mut *self.keys.hive.source.borrow_mut());
+                if let Err(error) = security_result {
+                    if let Some(Err(error)) = self.keys.report::<()>(nk.get_security_key_offset() as u64 + HBIN_START_OFFSET, &path, error) {
+                        pending_errors.push(error);
+                    }
+                }
+            }
+
+            let value_offsets_result = nk.get_value_offsets(&mut *self.keys.hive.source.borrow_mut());
+            let value_offsets = match value_offsets_result {
+                Ok(value_offsets) => value_offsets,
+                Err(error) => {
+                    let offset = nk.get_value_list_offset() as u64 + HBIN_START_OFFSET;
+                    if let Some(Err(error)) = self.keys.report::<()>(offset, &path, error) {
+                        pending_errors.push(error);
+                    }
+                    Vec::new()
+                }
+            };
+
+            self.current_key = Some(
+                ValueFrame {
+                    path: path,
+                    nk: nk,
+                    value_offsets: value_offsets,
+                    next_index: 0
+                }
+            );
+            self.pending_errors.extend(pending_errors);
+        }
     }
 }
@@ -0,0 +1,431 @@
+use hive::{Hive,HBIN_START_OFFSET};
+use cell::Cell;
+use cell::CellData;
+use nk::{NodeKey,NodeKeyFlags,LayerSemantics};
+use sk::SecurityKey;
+use nk::{AccessBits,UserFlags,VirtualizationFlags};
+use vk::{ValueKey,VkDataType,Data};
+use errors::RegError;
+use utils;
+use deleted;
+use rwinstructs::security::SecurityDescriptor;
+use rwinstructs::timestamp::{WinTimestamp};
+use std::io::{Read,Seek};
+
+/// The value holding the target path of a symbolic link key.
+pub const LINK_VALUE_NAME: &'static str = "SymbolicLinkValue";
+
+/// A key of a hive. Navigation reads from the hive it came from, so no reader needs
+/// to be passed around. The security descriptor and class name are read when asked for,
+/// so a bad cell of one key does not keep its siblings from being listed.
+pub struct Key<'a, Rs: 'a> {
+    hive: &'a Hive<Rs>,
+    path: String,
+    nk: NodeKey
+}
+impl <'a, Rs: Read+Seek> Key<'a, Rs> {
+    /// Wrap a node key read from a hive. The path is the full path of the key.
+    pub fn new(hive: &'a Hive<Rs>, nk: NodeKey, path: String) -> Key<'a, Rs> {
+        Key {
+            hive: hive,
+            path: path,
+            nk: nk
+        }
+    }
+
+    pub fn get_name(&self)->&str{
+        self.nk.key_name()
+    }
+
+    /// The full path of the key, starting with the root key. This is the path the key
+    /// was reached by, or the climbed path for keys from Hive::key_at_offset.
+    pub fn get_path(&self)->&str{
+        &self.path
+    }
+
+    /// Rebuild the full path of the key by climbing parent offsets to the root key,
+    /// without relying on how the key was reached. A chain that breaks or loops starts
+    /// with `[UNKNOWN]`.
+    pub fn path(&self)->String{
+        deleted::get_key_path(&mut *self.hive.borrow_source(), self.get_offset(), &self.nk)
+    }
+
+    /// The offset of the key's cell relative to the first hive bin.
+    pub fn get_offset(&self)->u32{
+        (self.nk.get_offset() - HBIN_START_OFFSET - 4) as u32
+    }
+
+    pub fn get_last_written(&self)->&WinTimestamp{
+        self.nk.get_last_written()
+    }
+
+    pub fn get_flags(&self)->&NodeKeyFlags{
+        self.nk.get_flags()
+    }
+
+    /// Whether the key was accessed during or after boot, from Windows 8 on.
+    pub fn get_access_bits(&self)->&AccessBits{
+        self.nk.get_access_bits()
+    }
+
+    pub fn get_user_flags(&self)->&UserFlags{
+        self.nk.get_user_flags()
+    }
+
+    pub fn get_virtualization_flags(&self)->&VirtualizationFlags{
+        self.nk.get_virtualization_flags()
+    }
+
+    /// Read the class name from its cell, or None when the key has none.
+    pub fn get_class_name(&self)->Result<Option<String>,RegError>{
+        self.nk.decode_class_name(&mut *self.hive.borrow_source())
+    }
+
+    /// Read the security descriptor from the key's security key, or None when the key
+    /// has none.
+    pub fn get_security(&self)->Result<Option<SecurityDescriptor>,RegError>{
+        match self.nk.get_security_id() {
+            Some(offset) => {
+                let sk = SecurityKey::at_offset(&mut *self.hive.borrow_source(), offset)?;
+                Ok(Some(sk.get_descriptor().clone()))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// How the key combines with the layers below it, for keys of version 1.6 hives.
+    pub fn get_layer_semantics(&self)->Option<LayerSemantics>{
+        self.nk.get_layer_semantics()
+    }
+
+    /// Check if the key is a symbolic link, such as CurrentControlSet.
+    pub fn is_link(&self)->bool{
+        self.nk.get_flags().contains(NodeKeyFlags::KEY_SYM_LINK)
+    }
+
+    /// The registry path a link key points to, from its SymbolicLinkValue, e.g.
+    /// \REGISTRY\MACHINE\SYSTEM\ControlSet001. None if the key is not a link.
+    /// registry::Registry follows it to a key.
+    pub fn get_link_target(&self)->Result<Option<String>,RegError>{
+        if !self.is_link() {
+            return Ok(None);
+        }
+
+        let value = match self.value(LINK_VALUE_NAME)? {
+            Some(value) => value,
+            None => {
+                return Err(
+                    RegError::validation_error(
+                        format!("Link key {} has no {} value.", self.path, LINK_VALUE_NAME)
+                    )
+                );
+            }
+        };
+
+        match value.get_data()? {
+            Some(Data::Link(target)) | Some(Data::String(target)) => Ok(Some(target)),
+            _ => {
+                Err(
+                    RegError::validation_error(
+                        format!("{} of link key {} is a {}, not a path.",
+                            LINK_VALUE_NAME, self.path, value.get_data_type().as_string()
+                        )
+                    )
+                )
+            }
+        }
+    }
+
+    /// The underlying node key.
+    pub fn get_node_key(&self)->&NodeKey{
+        &self.nk
+    }
+
+    /// The stable subkeys of this key, in the order of its subkey list. Volatile subkeys
+    /// are listed by volatile_subkeys.
+    pub fn subkeys(&self)->Result<Vec<Key<'a, Rs>>,RegError>{
+        let offsets = self.nk.get_sub_key_offsets(&mut *self.hive.borrow_source())?;
+        self.keys_at_offsets(offsets)
+    }
+
+    /// The volatile subkeys of this key, in the order of its volatile subkey list. A hive
+    /// file has no volatile storage, so a key with a volatile subkey list gives a
+    /// VolatileOffset error unless the hive came from Hive::from_memory.
+    pub fn volatile_subkeys(&self)->Result<Vec<Key<'a, Rs>>,RegError>{
+        if !self.nk.has_volatile_sub_keys() {
+            return Ok(Vec::new());
+        }
+        if !self.hive.has_volatile_storage() {
+            return Err(
+                RegError::volatile_offset(self.nk.get_volatile_sub_key_list_offset())
+            );
+        }
+
+        let offsets = self.nk.get_volatile_sub_key_offsets(&mut *self.hive.borrow_source())?;
+        self.keys_at_offsets(offsets)
+    }
+
+    fn keys_at_offsets(&self, offsets: Vec<u32>)->Result<Vec<Key<'a, Rs>>,RegError>{
+        let mut subkeys = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            let nk = self.hive.read_node_key(offset)?;
+            let path = format!("{}\\{}",self.path,nk.key_name());
+            subkeys.push(
+                Key::new(self.hive, nk, path)
+            );
+        }
+        Ok(subkeys)
+    }
+
+    /// Find a subkey by name. Names are compared case insensitively.
+    pub fn subkey(&self, name: &str)->Result<Option<Key<'a, Rs>>,RegError>{
+        let found = self.nk.find_sub_key(&mut *self.hive.borrow_source(), name)?;
+        match found {
+            Some(nk) => {
+                let path = format!("{}\\{}",self.path,nk.key_name());
+                Ok(Some(Key::new(self.hive, nk, path)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// The values of this key, in the order of its value list.
+    pub fn values(&self)->Result<Vec<Value<'a, Rs>>,RegError>{
+        let offsets = self.nk.get_value_offsets(&mut *self.hive.borrow_source())?;
+
+        let mut values = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            values.push(
+                Value::at_offset(self.hive, offset, self.get_offset(), &self.path)?
+            );
+        }
+        Ok(values)
+    }
+
+    /// Find a value by name. Names are compared case insensitively and the default
+    /// value has an empty name.
+    pub fn value(&self, name: &str)->Result<Option<Value<'a, Rs>>,RegError>{
+        for value in self.values()? {
+            if utils::names_equal(value.get_name(), name) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The parent of this key, read through its parent offset, or None for the root key.
+    pub fn parent(&self)->Result<Option<Key<'a, Rs>>,RegError>{
+        if self.nk.is_root() {
+            return Ok(None);
+        }
+
+        let parent_offset = self.nk.get_parent_key_offset();
+        let (nk, path) = {
+            let mut source = self.hive.borrow_source();
+            let mut nk = NodeKey::at_offset(&mut *source, parent_offset)?;
+            nk.set_version(self.nk.get_version());
+
+            // Drop the last path component, unless the parent offset leads somewhere else
+            let path = match self.path.rfind('\\') {
+                Some(index) if self.path[0..index].ends_with(&format!("\\{}",nk.key_name())) => {
+                    self.path[0..index].to_string()
+                },
+                _ => deleted::get_key_path(&mut *source, parent_offset, &nk)
+            };
+            (nk, path)
+        };
+        Ok(Some(Key::new(self.hive, nk, path)))
+    }
+}
+
+/// A value of a key. The data is read up front; a value whose data cannot be read is
+/// kept, and get_data returns the error.
+pub struct Value<'a, Rs: 'a> {
+    hive: &'a Hive<Rs>,
+    offset: u32,
+    key_offset: u32,
+    key_path: String,
+    vk: ValueKey,
+    data_error: Option<RegError>
+}
+impl <'a, Rs: Read+Seek> Value<'a, Rs> {
+    /// Read the value at an offset relative to the first hive bin, belonging to the key
+    /// at key_offset.
+    pub fn at_offset(hive: &'a Hive<Rs>, offset: u32, key_offset: u32, key_path: &str) -> Result<Value<'a, Rs>,RegError> {
+        let mut source = hive.borrow_source();
+        let cell = Cell::at_offset(&mut *source, offset as u64 + HBIN_START_OFFSET)?;
+        let mut vk = match cell.get_data()? {
+            CellData::ValueKey(vk) => vk,
+            other => {
+                return Err(
+                    RegError::unexpected_cell_type(cell.get_offset(), "vk", other.get_type_name())
+                );
+            }
+        };
+        vk.set_version(hive.get_version());
+        let data_error = vk.read_value(&mut *source).err();
+
+        Ok(
+            Value {
+                hive: hive,
+                offset: offset,
+                key_offset: key_offset,
+                key_path: key_path.to_string(),
+                vk: vk,
+                data_error: data_error
+            }
+        )
+    }
+
+    pub fn get_name(&self)->&str{
+        self.vk.get_name()
+    }
+
+    /// The full path of the value: the path of its key followed by its name.
+    pub fn get_path(&self)->String{
+        format!("{}\\{}",self.key_path,self.vk.get_name())
+    }
+
+    /// The offset of the value's cell relative to the first hive bin.
+    pub fn get_offset(&self)->u32{
+        self.offset
+    }
+
+    pub fn get_data_type(&self)->&VkDataType{
+        self.vk.get_data_type()
+    }
+
+    /// The value data exactly as stored. Empty when the data could not be read.
+    pub fn get_raw_data(&self)->&[u8]{
+        self.vk.get_raw_data()
+    }
+
+    /// The decoded value data, or the error met reading it.
+    pub fn get_data(&self)->Result<Option<Data>,RegError>{
+        if let Some(ref error) = self.data_error {
+            return Err(error.clone());
+        }
+        self.vk.decode_data()
+    }
+
+    /// Check if the value deletes the value of the same name in the layers below it.
+    pub fn is_tombstone(&self)->bool{
+        self.vk.is_tombstone()
+    }
+
+    /// The underlying value key.
+    pub fn get_value_key(&self)->&ValueKey{
+        &self.vk
+    }
+
+    /// The key this value belongs to.
+    pub fn key(&self)->Result<Key<'a, Rs>,RegError>{
+        let nk = self.hive.read_node_key(self.key_offset)?;
+        Ok(Key::new(self.hive, nk, self.key_path.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder,LittleEndian};
+    use std::io::{Read,Cursor};
+    use std::fs::File;
+
+    #[test]
+    fn key_navigation() {
+        let file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let hive = Hive::from_source(file).unwrap();
+
+        let root = hive.get_root_key().unwrap();
+        assert_eq!(root.get_offset(), 32);
+        assert!(root.parent().unwrap().is_none());
+        assert!(root.get_security().unwrap().is_some());
+        assert_eq!(*root.get_access_bits(), AccessBits::ACCESSED_AFTER_BOOT);
+
+        let subkeys = root.subkeys().unwrap();
+        assert_eq!(subkeys.len(), 13);
+        assert_eq!(subkeys[12].get_name(), "WXP");
+        assert_eq!(subkeys[12].parent().unwrap().unwrap().get_path(), root.get_path());
+
+        let software = root.subkey("SOFTWARE").unwrap().unwrap();
+        assert_eq!(software.get_path(), format!("{}\\Software",root.get_path()));
+        assert!(software.subkey("DoesNotExist").unwrap().is_none());
+
+        let settings = hive.get_key("Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings").unwrap();
+        let user_agent = settings.value("user agent").unwrap().unwrap();
+        assert_eq!(user_agent.get_offset(), 4680 - 4096);
+        assert_eq!(user_agent.get_data_type().as_string(), "REG_SZ");
+        assert_eq!(user_agent.get_path(), format!("{}\\User Agent",settings.get_path()));
+        match user_agent.get_data().unwrap() {
+            Some(Data::String(value)) => assert!(value.starts_with("Mozilla/")),
+            other => panic!("{:?}",other)
+        }
+        assert_eq!(user_agent.key().unwrap().get_offset(), settings.get_offset());
+        assert!(settings.values().unwrap().len() > 1);
+        assert!(settings.value("DoesNotExist").unwrap().is_none());
+        assert_eq!(settings.path(), settings.get_path());
+    }
+
+    #[test]
+    fn key_bad_cells() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        let wxp_offset = {
+            let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+            hive.get_key("WXP").unwrap().get_offset() as usize
+        };
+
+        // WXP points at the User Agent value key for its security key
+        let security = wxp_offset + 4096 + 4 + 44;
+        LittleEndian::write_u32(&mut buffer[security..security + 4], 4680 - 4096);
+        // User Agent has its data outside of the hive
+        let data = 4680 + 4 + 8;
+        LittleEndian::write_u32(&mut buffer[data..data + 4], 0x7FFFFFF0);
+
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+        let subkeys = hive.get_root_key().unwrap().subkeys().unwrap();
+        assert_eq!(subkeys.len(), 13);
+        assert!(subkeys[12].get_security().is_err());
+        assert!(subkeys[11].get_security().unwrap().is_some());
+
+        let settings = hive.get_key("Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings").unwrap();
+        let values = settings.values().unwrap();
+        assert!(values.len() > 1);
+        let user_agent = settings.value("User Agent").unwrap().unwrap();
+        assert!(user_agent.get_data().is_err());
+        assert!(user_agent.get_raw_data().is_empty());
+        assert!(values.iter().filter(|value| value.get_name() != "User Agent").all(|value| value.get_data().is_ok()));
+    }
+
+    #[test]
+    fn key_at_offset_parent_loop() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        let (run_offset, current_version_offset) = {
+            let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+            let run = hive.get_key("Software\\Microsoft\\Windows\\CurrentVersion\\Run").unwrap();
+            (run.get_offset() as usize, run.parent().unwrap().unwrap().get_offset() as usize)
+        };
+
+        // CurrentVersion and Run now point at each other
+        let parent = current_version_offset + 4096 + 4 + 16;
+        LittleEndian::write_u32(&mut buffer[parent..parent + 4], run_offset as u32);
+
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+        let run = hive.key_at_offset(run_offset as u32).unwrap();
+        assert_eq!(run.get_path(), "\\[UNKNOWN]\\CurrentVersion\\Run");
+        assert_eq!(run.path(), run.get_path());
+
+        let current_version = run.parent().unwrap().unwrap();
+        assert_eq!(current_version.get_path(), "\\[UNKNOWN]\\CurrentVersion");
+        assert_eq!(current_version.parent().unwrap().unwrap().get_offset(), run_offset as u32);
+
+        // Reached by name, the path is still the one walked
+        let run = hive.get_key("Software\\Microsoft\\Windows\\CurrentVersion\\Run").unwrap();
+        assert!(run.get_path().ends_with("\\Software\\Microsoft\\Windows\\CurrentVersion\\Run"));
+        assert!(run.path().starts_with("\\[UNKNOWN]"));
+    }
+}
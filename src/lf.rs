@@ -22,6 +22,17 @@ impl FastElement {
     pub fn get_offset(&self)->&u32{
         &self.0
     }
+
+    /// Check the name hint (the first 4 characters of the key name) against a name.
+    /// Names starting with non ASCII characters cannot be ruled out by their hint.
+    pub fn hint_matches(&self, name: &str)->bool{
+        let prefix: String = name.chars().take(4).collect();
+        if !prefix.is_ascii() {
+            return true;
+        }
+
+        self.1.trim_matches('\0').eq_ignore_ascii_case(&prefix)
+    }
 }
 
 // lf
@@ -37,13 +48,24 @@ pub struct FastLeaf{
 
 impl FastLeaf{
     pub fn new(buffer: &[u8], offset: u64) -> Result<FastLeaf,RegError> {
+        utils::check_len(buffer, 4, "FastLeaf", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let element_count = LittleEndian::read_u16(&buffer[2..4]);
+        if buffer.len() < 4 + element_count as usize * 8 {
+            return Err(
+                RegError::validation_error(
+                    format!("FastLeaf at offset {} has {} elements but only {} bytes.",
+                        offset, element_count, buffer.len()
+                    )
+                )
+            );
+        }
         let mut elements: Vec<FastElement> = Vec::new();
         let next_index: usize = 0;
 
-        for i in 0..element_count {
-            let o = (4 + (i*8)) as usize;
+        for i in 0..element_count as usize {
+            let o = 4 + i*8;
             let element = FastElement::new(
                 &buffer[o..o+8]
             )?;
@@ -61,21 +83,83 @@ impl FastLeaf{
         )
     }
 
+    /// The offsets of the node keys in this list.
+    pub fn get_offsets(&self)->Vec<u32>{
+        self.elements.iter().map(|e| *e.get_offset()).collect()
+    }
+
+    /// Find a node key by name, only reading the node keys whose name hint matches.
+    pub fn find_key<Rs: Read+Seek>(&self, reader: &mut Rs, name: &str)->Result<Option<NodeKey>,RegError>{
+        for element in self.elements.iter() {
+            if !element.hint_matches(name) {
+                continue;
+            }
+
+            let nk = NodeKey::at_offset(reader, *element.get_offset())?;
+            if utils::names_equal(nk.key_name(), name) {
+                return Ok(Some(nk));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn get_next_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<NodeKey>,RegError>{
         if self.next_index >= self.elements.len(){
             self.next_index = 0;
             Ok(None)
         }
-
         else {
             let cell_offset = (*self.elements[self.next_index].get_offset() as u64) + HBIN_START_OFFSET;
+            // Step past this element first so a bad cell is not returned again
+            self.next_index += 1;
             match Cell::at_offset(reader,cell_offset)?.get_data()?{
                 CellData::NodeKey(nk)=>{
-                    self.next_index += 1;
                     Ok(Some(nk))
                 },
-                other => panic!("CellData is not type NodeKey: {:?}",other)
+                other => Err(
+                    RegError::unexpected_cell_type(cell_offset, "nk", other.get_type_name())
+                )
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn fastleaf_find_key() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+
+        // Sub key list of the root key
+        let cell = Cell::at_offset(&mut file, 2587704 + HBIN_START_OFFSET).unwrap();
+        let lf = match cell.get_data().unwrap() {
+            CellData::FastLeaf(lf) => lf,
+            other => panic!("Expected FastLeaf: {:?}",other)
+        };
+
+        assert_eq!(lf.elements[10].1, "Soft");
+        assert_eq!(lf.elements[10].hint_matches("SOFTWARE"), true);
+        assert_eq!(lf.elements[10].hint_matches("System"), false);
+
+        let nk = lf.find_key(&mut file, "software").unwrap().unwrap();
+        assert_eq!(nk.key_name(), "Software");
+        let nk = lf.find_key(&mut file, "wxp").unwrap().unwrap();
+        assert_eq!(nk.key_name(), "WXP");
+        assert!(lf.find_key(&mut file, "Softwar").unwrap().is_none());
+    }
+
+    #[test]
+    fn fastleaf_max_elements() {
+        // Element offsets used to be computed in u16 and overflow past 8191 elements
+        let mut buffer = vec![0; 4 + 65535 * 8];
+        buffer[0..4].copy_from_slice(&[0x6C,0x66,0xFF,0xFF]);
+        let lf = FastLeaf::new(&buffer, 0).unwrap();
+        assert_eq!(lf.get_offsets().len(), 65535);
+
+        assert!(FastLeaf::new(&buffer[0..buffer.len() - 1], 0).is_err());
+    }
+}
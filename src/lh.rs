@@ -1,5 +1,6 @@
 use byteorder::{ByteOrder,LittleEndian};
 use errors::RegError;
+use utils;
 use hive::HBIN_START_OFFSET;
 use cell::Cell;
 use cell::CellData;
@@ -19,6 +20,10 @@ impl HashElement {
     pub fn get_offset(&self)->&u32{
         &self.0
     }
+
+    pub fn get_hash(&self)->&u32{
+        &self.1
+    }
 }
 
 // lh
@@ -33,13 +38,24 @@ pub struct HashLeaf{
 }
 impl HashLeaf{
     pub fn new(buffer: &[u8], offset: u64) -> Result<HashLeaf,RegError> {
+        utils::check_len(buffer, 4, "HashLeaf", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let element_count = LittleEndian::read_u16(&buffer[2..4]);
+        if buffer.len() < 4 + element_count as usize * 8 {
+            return Err(
+                RegError::validation_error(
+                    format!("HashLeaf at offset {} has {} elements but only {} bytes.",
+                        offset, element_count, buffer.len()
+                    )
+                )
+            );
+        }
         let mut elements: Vec<HashElement> = Vec::new();
         let next_index: usize = 0;
 
-        for i in 0..element_count {
-            let o = (4 + (i*8)) as usize;
+        for i in 0..element_count as usize {
+            let o = 4 + i*8;
             let element = HashElement::new(
                 &buffer[o..o+8]
             );
@@ -57,6 +73,28 @@ impl HashLeaf{
         )
     }
 
+    /// The offsets of the node keys in this list.
+    pub fn get_offsets(&self)->Vec<u32>{
+        self.elements.iter().map(|e| *e.get_offset()).collect()
+    }
+
+    /// Find a node key by name, only reading the node keys whose name hash matches.
+    pub fn find_key<Rs: Read+Seek>(&self, reader: &mut Rs, name: &str)->Result<Option<NodeKey>,RegError>{
+        let hash = utils::name_hash(name);
+        for element in self.elements.iter() {
+            if *element.get_hash() != hash {
+                continue;
+            }
+
+            let nk = NodeKey::at_offset(reader, *element.get_offset())?;
+            if utils::names_equal(nk.key_name(), name) {
+                return Ok(Some(nk));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn get_next_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<NodeKey>,RegError>{
         if self.next_index >= self.elements.len(){
             self.next_index = 0;
@@ -64,12 +102,14 @@ impl HashLeaf{
         }
         else {
             let cell_offset = (*self.elements[self.next_index].get_offset() as u64) + HBIN_START_OFFSET;
+            self.next_index += 1;
             match Cell::at_offset(reader,cell_offset)?.get_data()?{
                 CellData::NodeKey(nk)=>{
-                    self.next_index += 1;
                     Ok(Some(nk))
                 },
-                other => panic!("CellData is not type NodeKey: {:?}",other)
+                other => Err(
+                    RegError::unexpected_cell_type(cell_offset, "nk", other.get_type_name())
+                )
             }
         }
     }
@@ -1,10 +1,12 @@
 use byteorder::{ByteOrder,LittleEndian};
 use errors::RegError;
+use utils;
 use hive::HBIN_START_OFFSET;
 use cell::Cell;
 use cell::CellData;
 use nk::NodeKey;
 use std::io::{Read,Seek};
+use std::cmp::Ordering;
 
 // li
 #[derive(Serialize, Debug)]
@@ -19,13 +21,24 @@ pub struct IndexLeaf{
 
 impl IndexLeaf{
     pub fn new(buffer: &[u8], offset: u64) -> Result<IndexLeaf,RegError> {
+        utils::check_len(buffer, 4, "IndexLeaf", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let element_count = LittleEndian::read_u16(&buffer[2..4]);
+        if buffer.len() < 4 + element_count as usize * 4 {
+            return Err(
+                RegError::validation_error(
+                    format!("IndexLeaf at offset {} has {} elements but only {} bytes.",
+                        offset, element_count, buffer.len()
+                    )
+                )
+            );
+        }
         let mut elements: Vec<u32> = Vec::new();
         let next_index: usize = 0;
 
-        for i in 0..element_count {
-            let o = (4 + (i*4)) as usize;
+        for i in 0..element_count as usize {
+            let o = 4 + i*4;
             let element = LittleEndian::read_u32(&buffer[o..o+4]);
             elements.push(element);
         }
@@ -41,6 +54,28 @@ impl IndexLeaf{
         )
     }
 
+    /// The offsets of the node keys in this list.
+    pub fn get_offsets(&self)->Vec<u32>{
+        self.elements.clone()
+    }
+
+    /// Find a node key by name with a binary search, as the list is sorted by upper cased name.
+    pub fn find_key<Rs: Read+Seek>(&self, reader: &mut Rs, name: &str)->Result<Option<NodeKey>,RegError>{
+        let mut low = 0;
+        let mut high = self.elements.len();
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let nk = NodeKey::at_offset(reader, self.elements[middle])?;
+            match utils::compare_names(nk.key_name(), name) {
+                Ordering::Equal => return Ok(Some(nk)),
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn get_next_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<NodeKey>,RegError>{
         if self.next_index >= self.elements.len(){
             self.next_index = 0;
@@ -48,13 +83,43 @@ impl IndexLeaf{
         }
         else {
             let cell_offset = (self.elements[self.next_index] as u64) + HBIN_START_OFFSET;
+            self.next_index += 1;
             match Cell::at_offset(reader,cell_offset)?.get_data()?{
                 CellData::NodeKey(nk)=>{
-                    self.next_index += 1;
                     Ok(Some(nk))
                 },
-                other => panic!("CellData is not type NodeKey: {:?}",other)
+                other => Err(
+                    RegError::unexpected_cell_type(cell_offset, "nk", other.get_type_name())
+                )
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn indexleaf_find_key() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+
+        // Sub key list of Office\15.0\Common\Research\Sources\{62E1D68D-E1C4-4CC5-9D0C-D4B7999C4BE6}
+        let cell = Cell::at_offset(&mut file, 1081376 + HBIN_START_OFFSET).unwrap();
+        let li = match cell.get_data().unwrap() {
+            CellData::IndexLeaf(li) => li,
+            other => panic!("Expected IndexLeaf: {:?}",other)
+        };
+        assert_eq!(li.element_count, 896);
+
+        let nk = li.find_key(&mut file, "{0297cd20-047f-4256-0104-000009040000}").unwrap().unwrap();
+        assert_eq!(nk.key_name(), "{0297CD20-047F-4256-0104-000009040000}");
+
+        let first = NodeKey::at_offset(&mut file, li.elements[0]).unwrap();
+        let last = NodeKey::at_offset(&mut file, li.elements[895]).unwrap();
+        assert!(li.find_key(&mut file, first.key_name()).unwrap().is_some());
+        assert!(li.find_key(&mut file, last.key_name()).unwrap().is_some());
+        assert!(li.find_key(&mut file, "{0297CD20-047F-4256-0104-000009040001}").unwrap().is_none());
+    }
+}
@@ -7,11 +7,13 @@ extern crate rwinstructs;
 extern crate byteorder;
 extern crate encoding;
 extern crate serde;
+#[cfg(test)] extern crate serde_json;
 pub mod baseblock;
 pub mod record;
 pub mod errors;
 pub mod hive;
 pub mod utils;
+pub mod hbin;
 pub mod cell;
 pub mod vk;
 pub mod nk;
@@ -21,3 +23,12 @@ pub mod lf;
 pub mod li;
 pub mod ri;
 pub mod db;
+pub mod deleted;
+pub mod slack;
+pub mod transaction_log;
+pub mod diagnostic;
+pub mod verify;
+pub mod resource;
+pub mod key;
+pub mod volatile;
+pub mod registry;
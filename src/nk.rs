@@ -2,6 +2,8 @@ use byteorder::{ByteOrder,LittleEndian};
 use rwinstructs::timestamp::{WinTimestamp};
 use errors::RegError;
 use hive::HBIN_START_OFFSET;
+use baseblock::HiveVersion;
+use volatile::VOLATILE_START_OFFSET;
 use cell::Cell;
 use cell::CellData;
 use vk::ValueKey;
@@ -24,8 +26,6 @@ bitflags! {
         const KEY_VIRT_MIRRORED = 0x0080;
         const KEY_VIRT_TARGET = 0x0100;
         const KEY_VIRTUAL_STORE = 0x0200;
-        const KEY_UNKNOWN1 = 0x1000;
-        const KEY_UNKNOWN2 = 0x4000;
     }
 }
 impl fmt::Display for NodeKeyFlags {
@@ -41,6 +41,102 @@ impl ser::Serialize for NodeKeyFlags {
     }
 }
 
+/// How a key of a layered (version 1.6) hive, as used for containers, combines with the
+/// same key in the layers below it.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum LayerSemantics {
+    /// The key is merged with the keys below it
+    Normal,
+    /// The key is deleted in this layer and hides the keys below it
+    Tombstone,
+    /// The key replaces the keys below it, but their subkeys are still merged
+    SupersedeLocal,
+    /// The key and its subtree replace the keys below it
+    SupersedeTree
+}
+impl LayerSemantics {
+    pub fn new(bits: u8) -> LayerSemantics {
+        match bits & 0x03 {
+            1 => LayerSemantics::Tombstone,
+            2 => LayerSemantics::SupersedeLocal,
+            3 => LayerSemantics::SupersedeTree,
+            _ => LayerSemantics::Normal
+        }
+    }
+}
+
+bitflags! {
+    /// When the key was accessed, recorded from Windows 8 on.
+    pub struct AccessBits: u8 {
+        const ACCESSED_DURING_BOOT = 0x01;
+        const ACCESSED_AFTER_BOOT = 0x02;
+    }
+}
+impl ser::Serialize for AccessBits {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&format!("{:?}", self))
+    }
+}
+
+bitflags! {
+    /// Wow64 user flags.
+    pub struct UserFlags: u8 {
+        /// Created through Wow64, or not for 64-bit programs
+        const WOW64_32BIT_KEY = 0x01;
+        /// Created by reflection from the other view
+        const WOW64_REFLECTED = 0x02;
+        const WOW64_DISABLE_REFLECTION = 0x04;
+        /// Disable reflection when the key exists in the other view
+        const WOW64_DISABLE_REFLECTION_EXISTING = 0x08;
+    }
+}
+impl ser::Serialize for UserFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&format!("{:?}", self))
+    }
+}
+
+bitflags! {
+    /// Registry virtualization control flags.
+    pub struct VirtualizationFlags: u8 {
+        const REG_KEY_DONT_VIRTUALIZE = 0x02;
+        const REG_KEY_DONT_SILENT_FAIL = 0x04;
+        const REG_KEY_RECURSE_FLAG = 0x08;
+    }
+}
+impl ser::Serialize for VirtualizationFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&format!("{:?}", self))
+    }
+}
+
+bitflags! {
+    /// Debug flags: break into a kernel debugger on these operations.
+    pub struct DebugFlags: u8 {
+        const BREAK_ON_OPEN = 0x01;
+        const BREAK_ON_DELETE = 0x02;
+        const BREAK_ON_SECURITY_CHANGE = 0x04;
+        const BREAK_ON_CREATE_SUBKEY = 0x08;
+        const BREAK_ON_DELETE_SUBKEY = 0x10;
+        const BREAK_ON_SET_VALUE = 0x20;
+        const BREAK_ON_DELETE_VALUE = 0x40;
+        const BREAK_ON_KEY_VIRTUALIZE = 0x80;
+    }
+}
+impl ser::Serialize for DebugFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&format!("{:?}", self))
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct NodeKey {
     #[serde(skip_serializing)]
@@ -48,7 +144,9 @@ pub struct NodeKey {
     signature: u16,
     flags: NodeKeyFlags,
     last_written: WinTimestamp,
-    access_bits: u32,
+    access_bits: AccessBits,
+    layered_key_bits: u8, // layer semantics and inherit class, version 1.6
+    spare: u16,
     offset_parent_key: u32,
     num_sub_keys: u32, // node keys
     num_volatile_sub_keys: u32,
@@ -58,7 +156,10 @@ pub struct NodeKey {
     offset_value_list: u32, //0xffffffff = empty
     offset_security_key: u32, //0xffffffff = empty
     offset_class_name: u32, //0xffffffff = empty
-    largest_sub_key_name_size: u32,
+    largest_sub_key_name_size: u16,
+    user_flags: UserFlags,
+    virtualization_flags: VirtualizationFlags,
+    debug_flags: DebugFlags,
     largest_sub_key_class_name_size: u32,
     largest_value_name_size: u32,
     largest_value_data_size: u32,
@@ -68,22 +169,28 @@ pub struct NodeKey {
     // 76 bytes
     key_name: String,
     padding: Vec<u8>,
+    class_name: Option<String>,
 
     value_key_list: Option<Box<ValueKeyList>>,
     sub_key_list: Option<Box<CellData>>,
-    security_key: Option<Box<SecurityKey>>
+    security_key: Option<Box<SecurityKey>>,
+    #[serde(skip_serializing)]
+    version: HiveVersion
 }
 impl NodeKey {
     pub fn new(buffer: &[u8], offset: u64) -> Result<NodeKey,RegError> {
+        utils::check_len(buffer, 76, "NodeKey", offset)?;
+
         let _offset = offset;
         let signature = LittleEndian::read_u16(&buffer[0..2]);
-        let flags = NodeKeyFlags::from_bits_truncate(
-            LittleEndian::read_u16(&buffer[2..4])
-        );
+        let raw_flags = LittleEndian::read_u16(&buffer[2..4]);
+        let flags = NodeKeyFlags::from_bits_truncate(raw_flags);
         let last_written = WinTimestamp(
             LittleEndian::read_u64(&buffer[4..12])
         );
-        let access_bits = LittleEndian::read_u32(&buffer[12..16]);
+        let access_bits = AccessBits::from_bits_truncate(buffer[12]);
+        let layered_key_bits = buffer[13];
+        let spare = LittleEndian::read_u16(&buffer[14..16]);
         let offset_parent_key = LittleEndian::read_u32(&buffer[16..20]);
         let num_sub_keys = LittleEndian::read_u32(&buffer[20..24]);
         let num_volatile_sub_keys = LittleEndian::read_u32(&buffer[24..28]);
@@ -93,7 +200,15 @@ impl NodeKey {
         let offset_value_list = LittleEndian::read_u32(&buffer[40..44]);
         let offset_security_key = LittleEndian::read_u32(&buffer[44..48]);
         let offset_class_name = LittleEndian::read_u32(&buffer[48..52]);
-        let largest_sub_key_name_size = LittleEndian::read_u32(&buffer[52..56]);
+        // From Vista on the user flags are in bits 16-19 of this field, followed by the
+        // virtualization control and debug flags. XP and 2003 kept the user flags in bits
+        // 12-15 of the key flags.
+        let largest_sub_key_name_size = LittleEndian::read_u16(&buffer[52..54]);
+        let user_flags = UserFlags::from_bits_truncate(
+            (buffer[54] & 0x0F) | (raw_flags >> 12) as u8
+        );
+        let virtualization_flags = VirtualizationFlags::from_bits_truncate(buffer[54] >> 4);
+        let debug_flags = DebugFlags::from_bits_truncate(buffer[55]);
         let largest_sub_key_class_name_size = LittleEndian::read_u32(&buffer[56..60]);
         let largest_value_name_size = LittleEndian::read_u32(&buffer[60..64]);
         let largest_value_data_size = LittleEndian::read_u32(&buffer[64..68]);
@@ -101,12 +216,23 @@ impl NodeKey {
         let key_name_size = LittleEndian::read_u16(&buffer[72..74]);
         let class_name_size = LittleEndian::read_u16(&buffer[74..76]);
 
+        if buffer.len() < 76 + key_name_size as usize {
+            return Err(
+                RegError::validation_error(
+                    format!("NodeKey at offset {} has a key name size of {} but only {} bytes.",
+                        offset, key_name_size, buffer.len()
+                    )
+                )
+            );
+        }
+
         let key_name = match flags.contains(NodeKeyFlags::KEY_COMP_NAME) {
-            true => utils::read_ascii(&buffer[76..(76 + key_name_size) as usize])?,
-            false => utils::read_utf16(&buffer[76..(76 + key_name_size) as usize])?
+            true => utils::read_ascii(&buffer[76..76 + key_name_size as usize])?,
+            false => utils::read_utf16(&buffer[76..76 + key_name_size as usize])?
         };
 
-        let padding = buffer[(76 + key_name_size) as usize..].to_vec();
+        let padding = buffer[76 + key_name_size as usize..].to_vec();
+        let class_name = None;
         let value_key_list = None;
         let sub_key_list = None;
         let security_key = None;
@@ -118,6 +244,8 @@ impl NodeKey {
                 flags: flags,
                 last_written: last_written,
                 access_bits: access_bits,
+                layered_key_bits: layered_key_bits,
+                spare: spare,
                 offset_parent_key: offset_parent_key,
                 num_sub_keys: num_sub_keys,
                 num_volatile_sub_keys: num_volatile_sub_keys,
@@ -128,6 +256,9 @@ impl NodeKey {
                 offset_security_key: offset_security_key,
                 offset_class_name: offset_class_name,
                 largest_sub_key_name_size: largest_sub_key_name_size,
+                user_flags: user_flags,
+                virtualization_flags: virtualization_flags,
+                debug_flags: debug_flags,
                 largest_sub_key_class_name_size: largest_sub_key_class_name_size,
                 largest_value_name_size: largest_value_name_size,
                 largest_value_data_size: largest_value_data_size,
@@ -136,17 +267,134 @@ impl NodeKey {
                 class_name_size: class_name_size,
                 key_name: key_name,
                 padding: padding,
+                class_name: class_name,
                 value_key_list: value_key_list,
                 sub_key_list: sub_key_list,
-                security_key: security_key
+                security_key: security_key,
+                version: HiveVersion::latest()
             }
         )
     }
 
+    /// Set the format version of the hive the key is from, which decides the subkey lists
+    /// it accepts and whether it has layered key fields. Defaults to the latest version.
+    pub fn set_version(&mut self, version: HiveVersion){
+        self.version = version;
+    }
+
+    pub fn get_version(&self)->HiveVersion{
+        self.version
+    }
+
+    /// The layer semantics of a key in a version 1.6 hive (bits 0-1 of the byte after
+    /// the access bits), or None for older versions.
+    pub fn get_layer_semantics(&self)->Option<LayerSemantics>{
+        match self.version.has_layered_keys() {
+            true => Some(LayerSemantics::new(self.layered_key_bits)),
+            false => None
+        }
+    }
+
+    /// A key in a version 1.6 hive can take its class name from the layer below it.
+    pub fn inherits_class(&self)->bool{
+        self.version.has_layered_keys() && self.layered_key_bits & 0x80 != 0
+    }
+
+    /// The absolute offset of the nk record, just past its cell size.
+    pub fn get_offset(&self)->u64{
+        self._offset
+    }
+
     pub fn key_name(&self)->&String{
         &self.key_name
     }
 
+    /// The bytes of the cell after the key name.
+    pub fn get_padding(&self)->&[u8]{
+        &self.padding
+    }
+
+    pub fn get_flags(&self)->&NodeKeyFlags{
+        &self.flags
+    }
+
+    /// Whether the key was accessed during or after boot, from Windows 8 on.
+    pub fn get_access_bits(&self)->&AccessBits{
+        &self.access_bits
+    }
+
+    pub fn get_user_flags(&self)->&UserFlags{
+        &self.user_flags
+    }
+
+    pub fn get_virtualization_flags(&self)->&VirtualizationFlags{
+        &self.virtualization_flags
+    }
+
+    pub fn get_debug_flags(&self)->&DebugFlags{
+        &self.debug_flags
+    }
+
+    /// The root key of a hive is flagged as the hive entry.
+    pub fn is_root(&self)->bool{
+        self.flags.contains(NodeKeyFlags::KEY_HIVE_ENTRY)
+    }
+
+    pub fn get_parent_key_offset(&self)->u32{
+        self.offset_parent_key
+    }
+
+    pub fn get_num_sub_keys(&self)->u32{
+        self.num_sub_keys
+    }
+
+    pub fn get_value_list_offset(&self)->u32{
+        self.offset_value_list
+    }
+
+    pub fn get_sub_key_list_offset(&self)->u32{
+        self.offset_sub_key_list
+    }
+
+    pub fn get_security_key_offset(&self)->u32{
+        self.offset_security_key
+    }
+
+    /// The offset of the security key, or None when the key has none.
+    pub fn get_security_id(&self)->Option<u32>{
+        match self.offset_security_key {
+            4294967295 => None,
+            offset => Some(offset)
+        }
+    }
+
+    pub fn get_class_name_offset(&self)->u32{
+        self.offset_class_name
+    }
+
+    /// Get the offsets of all values without touching the get_next_value cursor.
+    pub fn get_value_offsets<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Vec<u32>,RegError>{
+        if self.offset_value_list == 4294967295 || self.num_values == 0 {
+            return Ok(Vec::new());
+        }
+
+        let cell = Cell::at_offset(
+            reader,
+            self.offset_value_list as u64 + HBIN_START_OFFSET
+        )?;
+        let value_key_list = ValueKeyList::new(
+            &cell.data,
+            self.num_values,
+            self.offset_value_list as u64
+        )?;
+
+        Ok(value_key_list.get_offsets().clone())
+    }
+
+    pub fn get_num_values(&self)->u32{
+        self.num_values
+    }
+
     pub fn get_next_value<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<ValueKey>,RegError>{
         if self.offset_value_list == 4294967295 {
             return Ok(None);
@@ -170,19 +418,13 @@ impl NodeKey {
 
         match self.value_key_list{
             Some(ref mut value_key_list)=>{
-                match value_key_list.get_next_value(reader)?{
-                    Some(vk) => {
-                        Ok(Some(vk))
-                    },
-                    None => {
-                        Ok(None)
-                    }
-                }
+                value_key_list.get_next_value(reader)
             },
-            _ => panic!("Should already have a value")
+            None => Ok(None)
         }
     }
 
+    /// Step through the stable subkey list. Volatile subkeys are not included.
     pub fn get_next_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<NodeKey>,RegError>{
         if self.offset_sub_key_list == 4294967295 {
             return Ok(None);
@@ -193,8 +435,10 @@ impl NodeKey {
                 reader,
                 self.offset_sub_key_list as u64 + HBIN_START_OFFSET
             )?;
+            let cell_data = cell.get_data()?;
+            self.version.check_sub_key_list(&cell_data, cell.get_offset())?;
             self.sub_key_list = Some(
-                Box::new(cell.get_data()?)
+                Box::new(cell_data)
             );
         }
 
@@ -202,54 +446,173 @@ impl NodeKey {
             Some(ref mut cell_data) => {
                 match **cell_data {
                     CellData::RootIndex(ref mut ri) => {
-                        return Ok(
-                            ri.get_next_key(reader)?
-                        );
+                        ri.get_next_key(reader)
                     },
                     CellData::FastLeaf(ref mut lf) => {
-                        return Ok(
-                            lf.get_next_key(reader)?
-                        );
+                        lf.get_next_key(reader)
                     },
                     CellData::HashLeaf(ref mut lh) => {
-                        return Ok(
-                            lh.get_next_key(reader)?
-                        );
+                        lh.get_next_key(reader)
                     },
                     CellData::IndexLeaf(ref mut li) => {
-                        return Ok(
-                            li.get_next_key(reader)?
-                        );
+                        li.get_next_key(reader)
                     },
                     ref other => {
-                        panic!("Unhandled sub key list: {:?}",other);
+                        Err(
+                            RegError::unexpected_cell_type(
+                                self.offset_sub_key_list as u64 + HBIN_START_OFFSET,
+                                "lf, lh, li or ri",
+                                other.get_type_name()
+                            )
+                        )
                     }
                 }
             },
-            None => {
-                panic!("Subkey List is None.")
+            None => Ok(None)
+        }
+    }
+
+    /// Get the offsets of all subkeys without touching the get_next_key cursor. Only the
+    /// stable subkey list is read; see get_volatile_sub_key_offsets.
+    pub fn get_sub_key_offsets<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Vec<u32>,RegError>{
+        if self.offset_sub_key_list == 4294967295 {
+            return Ok(Vec::new());
+        }
+
+        read_sub_key_list(reader, self.offset_sub_key_list, &self.version)
+    }
+
+    pub fn get_num_volatile_sub_keys(&self)->u32{
+        self.num_volatile_sub_keys
+    }
+
+    pub fn get_volatile_sub_key_list_offset(&self)->u32{
+        self.offset_volatile_sub_key_list
+    }
+
+    /// Check if the key has a volatile subkey list to follow.
+    pub fn has_volatile_sub_keys(&self)->bool{
+        self.num_volatile_sub_keys != 0 && self.offset_volatile_sub_key_list != 4294967295
+    }
+
+    /// Get the offsets of the volatile subkeys. The list is in volatile storage, so the
+    /// reader has to be a volatile::VolatileSource; a hive file does not have it.
+    pub fn get_volatile_sub_key_offsets<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Vec<u32>,RegError>{
+        if !self.has_volatile_sub_keys() {
+            return Ok(Vec::new());
+        }
+
+        read_sub_key_list(reader, self.offset_volatile_sub_key_list, &self.version)
+    }
+
+    /// Check if the key is volatile: flagged as such, or read from volatile storage.
+    pub fn is_volatile(&self)->bool{
+        self.flags.contains(NodeKeyFlags::KEY_IS_VOLATILE) ||
+        self._offset >= VOLATILE_START_OFFSET
+    }
+
+    /// Read the node key at an offset relative to the first hive bin.
+    pub fn at_offset<Rs: Read+Seek>(reader: &mut Rs, offset: u32)->Result<NodeKey,RegError>{
+        let cell = Cell::at_offset(
+            reader,
+            offset as u64 + HBIN_START_OFFSET
+        )?;
+        match cell.get_data()? {
+            CellData::NodeKey(nk) => Ok(nk),
+            other => {
+                Err(
+                    RegError::unexpected_cell_type(cell.get_offset(), "nk", other.get_type_name())
+                )
             }
         }
     }
 
-    pub fn set_security_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<(),RegError>{
-        if self.offset_security_key == 4294967295 {
-            return Ok(());
+    /// Find a subkey by name. Names are compared case insensitively.
+    ///
+    /// Only the node keys whose lh hash or lf hint match the name are read, and
+    /// li and ri lists are binary searched.
+    pub fn find_sub_key<Rs: Read+Seek>(&self, reader: &mut Rs, name: &str)->Result<Option<NodeKey>,RegError>{
+        if self.offset_sub_key_list == 4294967295 {
+            return Ok(None);
         }
 
-        let cell = Cell::at_offset(reader, self.offset_security_key as u64 + HBIN_START_OFFSET)?;
-        match cell.get_data()?{
-            CellData::SecurityKey(sk) => {
-                self.security_key = Some(Box::new(sk));
-            },
+        let cell = Cell::at_offset(
+            reader,
+            self.offset_sub_key_list as u64 + HBIN_START_OFFSET
+        )?;
+
+        let cell_data = cell.get_data()?;
+        self.version.check_sub_key_list(&cell_data, cell.get_offset())?;
+        let found = match cell_data {
+            CellData::RootIndex(ri) => ri.find_key(reader, name)?,
+            CellData::FastLeaf(lf) => lf.find_key(reader, name)?,
+            CellData::HashLeaf(lh) => lh.find_key(reader, name)?,
+            CellData::IndexLeaf(li) => li.find_key(reader, name)?,
             other => {
-                panic!("Unexpected SK type: {:?}",other);
+                return Err(
+                    RegError::unexpected_cell_type(cell.get_offset(), "lf, lh, li or ri", other.get_type_name())
+                );
             }
+        };
+
+        Ok(found.map(|mut nk| {
+            nk.set_version(self.version);
+            nk
+        }))
+    }
+
+    pub fn set_security_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<(),RegError>{
+        if self.offset_security_key == 4294967295 {
+            return Ok(());
         }
 
+        let sk = SecurityKey::at_offset(reader, self.offset_security_key)?;
+        self.security_key = Some(Box::new(sk));
+
+        Ok(())
+    }
+
+    /// Read the class name from its cell. Class names are UTF-16 strings, e.g. the
+    /// LSA JD, Skew1, GBG and Data keys of a SYSTEM hive keep the boot key in theirs.
+    pub fn read_class_name<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<(),RegError>{
+        self.class_name = self.decode_class_name(reader)?;
         Ok(())
     }
 
+    /// Read the class name from its cell without keeping it. Class names that are not
+    /// clean UTF-16 are hex encoded.
+    pub fn decode_class_name<Rs: Read+Seek>(&self, reader: &mut Rs)->Result<Option<String>,RegError>{
+        if self.offset_class_name == 4294967295 || self.class_name_size == 0 {
+            return Ok(None);
+        }
+
+        let cell = Cell::at_offset(reader, self.offset_class_name as u64 + HBIN_START_OFFSET)?;
+        if cell.data.len() < self.class_name_size as usize {
+            return Err(
+                RegError::validation_error(
+                    format!("Class name at offset {} has {} bytes but a size of {}.",
+                        self.offset_class_name, cell.data.len(), self.class_name_size
+                    )
+                )
+            );
+        }
+
+        // Some class names hold binary data, such as the Lsa boot key fragments
+        let data = &cell.data[0..self.class_name_size as usize];
+        let (value, valid) = utils::read_utf16_lossy(data);
+        let value = value.trim_end_matches('\u{0}').to_string();
+        match valid && data.len() % 2 == 0 && !value.chars().any(|c| c.is_control()) {
+            true => Ok(Some(value)),
+            false => Ok(Some(utils::to_hex_string(data)))
+        }
+    }
+
+    /// The class name, once it has been read with read_class_name. A class name that is
+    /// not clean UTF-16 text is a hex string of its bytes.
+    pub fn get_class_name(&self)->&Option<String>{
+        &self.class_name
+    }
+
     pub fn get_security_key(&self)->&Option<Box<SecurityKey>>{
         &self.security_key
     }
@@ -259,10 +622,55 @@ impl NodeKey {
     }
 }
 
+/// Read the subkey offsets of an lf, lh, li or ri list at an offset relative to the
+/// first hive bin, checking that the lists are valid for the hive version.
+fn read_sub_key_list<Rs: Read+Seek>(reader: &mut Rs, offset: u32, version: &HiveVersion)->Result<Vec<u32>,RegError>{
+    let cell = Cell::at_offset(
+        reader,
+        offset as u64 + HBIN_START_OFFSET
+    )?;
+    let cell_data = cell.get_data()?;
+    version.check_sub_key_list(&cell_data, cell.get_offset())?;
+
+    match cell_data {
+        CellData::RootIndex(ri) => {
+            let mut offsets = Vec::new();
+            for list_offset in ri.get_offsets() {
+                let list_cell = Cell::at_offset(
+                    reader,
+                    list_offset as u64 + HBIN_START_OFFSET
+                )?;
+                let list_cell_data = list_cell.get_data()?;
+                version.check_sub_key_list(&list_cell_data, list_cell.get_offset())?;
+                match list_cell_data {
+                    CellData::FastLeaf(lf) => offsets.extend(lf.get_offsets()),
+                    CellData::HashLeaf(lh) => offsets.extend(lh.get_offsets()),
+                    CellData::IndexLeaf(li) => offsets.extend(li.get_offsets()),
+                    other => {
+                        return Err(
+                            RegError::unexpected_cell_type(list_cell.get_offset(), "lf, lh or li", other.get_type_name())
+                        );
+                    }
+                }
+            }
+            Ok(offsets)
+        },
+        CellData::FastLeaf(lf) => Ok(lf.get_offsets()),
+        CellData::HashLeaf(lh) => Ok(lh.get_offsets()),
+        CellData::IndexLeaf(li) => Ok(li.get_offsets()),
+        other => {
+            Err(
+                RegError::unexpected_cell_type(cell.get_offset(), "lf, lh, li or ri", other.get_type_name())
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use cell::Cell;
+    use hive::Hive;
     use std::io::Read;
     use std::io::Cursor;
     use std::fs::File;
@@ -290,7 +698,8 @@ mod tests {
         assert_eq!(nk.signature, 27502);
         assert_eq!(nk.flags.bits(), 44);
         assert_eq!(nk.last_written.0, 130269705849853298);
-        assert_eq!(nk.access_bits, 2);
+        assert_eq!(nk.access_bits, AccessBits::ACCESSED_AFTER_BOOT);
+        assert_eq!(nk.layered_key_bits, 0);
         assert_eq!(nk.offset_parent_key, 1928);
         assert_eq!(nk.num_sub_keys, 13);
         assert_eq!(nk.num_volatile_sub_keys, 1);
@@ -301,6 +710,9 @@ mod tests {
         assert_eq!(nk.offset_security_key, 7568);
         assert_eq!(nk.offset_class_name, 4294967295);
         assert_eq!(nk.largest_sub_key_name_size, 42);
+        assert!(nk.user_flags.is_empty());
+        assert!(nk.virtualization_flags.is_empty());
+        assert!(nk.debug_flags.is_empty());
         assert_eq!(nk.largest_sub_key_class_name_size, 0);
         assert_eq!(nk.largest_value_name_size, 0);
         assert_eq!(nk.largest_value_data_size, 0);
@@ -314,4 +726,64 @@ mod tests {
         ];
         assert_eq!(&nk.padding[..], known_data);
     }
+
+    #[test]
+    fn nodekey_header_flags() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER_4128_144_CELL_NK.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        // A user flag in the old location of the key flags, access bits, and the user,
+        // virtualization and debug flags after the largest subkey name size
+        buffer[4 + 3] |= 0x10;
+        buffer[4 + 12] = 0x03;
+        buffer[4 + 54] = 0x24;
+        buffer[4 + 55] = 0x01;
+        let cell = Cell::new(&mut Cursor::new(&buffer),0).unwrap();
+        let nk = NodeKey::new(&cell.data,4).unwrap();
+
+        assert_eq!(nk.get_flags().bits(), 44);
+        assert_eq!(*nk.get_access_bits(), AccessBits::ACCESSED_DURING_BOOT | AccessBits::ACCESSED_AFTER_BOOT);
+        assert_eq!(*nk.get_user_flags(), UserFlags::WOW64_32BIT_KEY | UserFlags::WOW64_DISABLE_REFLECTION);
+        assert_eq!(*nk.get_virtualization_flags(), VirtualizationFlags::REG_KEY_DONT_VIRTUALIZE);
+        assert_eq!(*nk.get_debug_flags(), DebugFlags::BREAK_ON_OPEN);
+        assert_eq!(nk.largest_sub_key_name_size, 42);
+    }
+
+    #[test]
+    fn nodekey_layer_semantics() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER_4128_144_CELL_NK.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        // Supersede tree and inherit class, after the cell size and access bits
+        buffer[4 + 13] = 0x83;
+        let cell = Cell::new(&mut Cursor::new(&buffer),0).unwrap();
+        let mut nk = NodeKey::new(&cell.data,4).unwrap();
+        assert_eq!(nk.get_layer_semantics(), Some(LayerSemantics::SupersedeTree));
+        assert!(nk.inherits_class());
+
+        nk.set_version(HiveVersion::new(1, 5));
+        assert_eq!(nk.get_layer_semantics(), None);
+        assert!(!nk.inherits_class());
+        assert_eq!(LayerSemantics::new(1), LayerSemantics::Tombstone);
+    }
+
+    #[test]
+    fn nodekey_binary_class_name() {
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        let class_name_offset = {
+            let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+            let nk = hive.open_key("Software\\Microsoft\\IMEMIP").unwrap();
+            assert_eq!(nk.get_class_name(), &Some("Software\\Microsoft\\IMEMIP\\0x0409".to_string()));
+            nk.get_class_name_offset() as usize + 4096 + 4
+        };
+
+        // An unpaired surrogate cannot be decoded, so the bytes are kept as hex
+        buffer[class_name_offset..class_name_offset + 2].copy_from_slice(&[0x00,0xD8]);
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+        let nk = hive.open_key("Software\\Microsoft\\IMEMIP").unwrap();
+        let class_name = nk.get_class_name().clone().unwrap();
+        assert!(class_name.starts_with("00D86F00"));
+    }
 }
@@ -1,39 +1,153 @@
 use vk::ValueKey;
-use nk::NodeKey;
+use nk::{NodeKey,NodeKeyFlags,LayerSemantics};
+use nk::{AccessBits,UserFlags,VirtualizationFlags};
+use sk::SecurityKey;
 use rwinstructs::security::SecurityDescriptor;
 use rwinstructs::timestamp::{WinTimestamp};
 
+/// The path segment used when a deleted key cannot be traced back to the root key.
+pub const UNKNOWN_PATH: &'static str = "[UNKNOWN]";
+
 #[derive(Serialize,Debug)]
 pub struct Record {
     pub fullpath: String,
     pub nk_last_written: WinTimestamp,
     pub valuekey: ValueKey,
-    pub security: Option<Box<SecurityDescriptor>>
+    pub security: Option<Box<SecurityDescriptor>>,
+    /// The offset of the security key, in place of the descriptor, see Hive::security_keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_id: Option<u32>
 }
 impl Record {
     pub fn new(path: &str, nk: &NodeKey, vk: ValueKey)->Record{
         let mut fullpath = path.to_string();
         fullpath.push_str(&format!("\\{}",vk.get_name()));
-        let mut security = None;
-
-        let nk_last_written = nk.get_last_written().clone();
-
-        match *nk.get_security_key() {
-            Some(ref sk) => {
-                security = Some(
-                    Box::new(
-                        sk.get_descriptor().clone()
-                    )
-                )
-            },
-            None => {}
-        }
 
         Record {
             fullpath: fullpath,
-            nk_last_written: nk_last_written,
+            nk_last_written: nk.get_last_written().clone(),
             valuekey: vk,
-            security: security
+            security: get_security(nk),
+            security_id: None
+        }
+    }
+}
+
+/// A key or value recovered from unallocated cells. What could not be recovered is left
+/// out: the last written time of a value without a key, and the value of a key whose
+/// values are gone.
+#[derive(Serialize,Debug)]
+pub struct DeletedRecord {
+    pub fullpath: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nk_last_written: Option<WinTimestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valuekey: Option<ValueKey>,
+    pub security: Option<Box<SecurityDescriptor>>,
+    /// Always true, so recovered records stand out among live records in JSON output
+    pub deleted: bool
+}
+impl DeletedRecord {
+    pub fn new(path: &str, nk: &NodeKey, vk: ValueKey)->DeletedRecord{
+        let mut record = DeletedRecord::from_key(path, nk);
+        record.fullpath.push_str(&format!("\\{}",vk.get_name()));
+        record.valuekey = Some(vk);
+        record
+    }
+
+    /// A record for a key without a value, such as a recovered key whose values are gone.
+    pub fn from_key(path: &str, nk: &NodeKey)->DeletedRecord{
+        DeletedRecord {
+            fullpath: path.to_string(),
+            nk_last_written: Some(nk.get_last_written().clone()),
+            valuekey: None,
+            security: get_security(nk),
+            deleted: true
+        }
+    }
+
+    /// A record for a value whose key is unknown.
+    pub fn from_orphan_value(vk: ValueKey)->DeletedRecord{
+        DeletedRecord {
+            fullpath: format!("\\{}\\{}",UNKNOWN_PATH,vk.get_name()),
+            nk_last_written: None,
+            valuekey: Some(vk),
+            security: None,
+            deleted: true
+        }
+    }
+}
+
+/// A copy of the descriptor of a key's security key, if it was read.
+fn get_security(nk: &NodeKey)->Option<Box<SecurityDescriptor>>{
+    match *nk.get_security_key() {
+        Some(ref sk) => Some(
+            Box::new(
+                sk.get_descriptor().clone()
+            )
+        ),
+        None => None
+    }
+}
+
+/// A record for a key itself, so that every key shows up in a timeline whether or not
+/// it has values.
+#[derive(Serialize,Debug)]
+pub struct KeyRecord {
+    pub fullpath: String,
+    pub last_written: WinTimestamp,
+    pub flags: NodeKeyFlags,
+    /// Whether the key was accessed during or after boot
+    pub access_bits: AccessBits,
+    pub user_flags: UserFlags,
+    pub virtualization_flags: VirtualizationFlags,
+    pub num_sub_keys: u32,
+    pub num_volatile_sub_keys: u32,
+    pub num_values: u32,
+    pub class_name: Option<String>,
+    pub security: Option<Box<SecurityDescriptor>>,
+    /// The offset of the security key, in place of the descriptor, see Hive::security_keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_id: Option<u32>,
+    pub volatile: bool,
+    /// Only for keys of version 1.6 hives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer_semantics: Option<LayerSemantics>
+}
+impl KeyRecord {
+    pub fn new(path: &str, nk: &NodeKey)->KeyRecord{
+        KeyRecord {
+            fullpath: path.to_string(),
+            last_written: nk.get_last_written().clone(),
+            flags: nk.get_flags().clone(),
+            access_bits: nk.get_access_bits().clone(),
+            user_flags: nk.get_user_flags().clone(),
+            virtualization_flags: nk.get_virtualization_flags().clone(),
+            num_sub_keys: nk.get_num_sub_keys(),
+            num_volatile_sub_keys: nk.get_num_volatile_sub_keys(),
+            num_values: nk.get_num_values(),
+            class_name: nk.get_class_name().clone(),
+            security: get_security(nk),
+            security_id: None,
+            volatile: nk.is_volatile(),
+            layer_semantics: nk.get_layer_semantics()
+        }
+    }
+}
+
+/// A security descriptor shared by keys, for records that refer to it by security_id.
+#[derive(Serialize,Debug)]
+pub struct SecurityRecord {
+    pub security_id: u32,
+    pub reference_count: u32,
+    pub descriptor: SecurityDescriptor
+}
+impl SecurityRecord {
+    pub fn new(offset: u32, sk: &SecurityKey)->SecurityRecord{
+        SecurityRecord {
+            security_id: offset,
+            reference_count: sk.get_reference_count(),
+            descriptor: sk.get_descriptor().clone()
         }
     }
 }
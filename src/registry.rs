@@ -0,0 +1,248 @@
+use hive::Hive;
+use key::Key;
+use errors::RegError;
+use utils;
+use std::collections::HashSet;
+use std::io::{Read,Seek};
+
+/// A hive mounted at a registry path.
+struct Mount<'a, Rs: 'a> {
+    path: String,
+    components: Vec<String>,
+    hive: &'a Hive<Rs>
+}
+
+/// A view of hives mounted at their registry paths, e.g. a SYSTEM hive at
+/// \REGISTRY\MACHINE\SYSTEM, so that full registry paths and the symbolic links
+/// between keys can be resolved. A single hive mounted on its own resolves the links
+/// within it.
+pub struct Registry<'a, Rs: 'a> {
+    mounts: Vec<Mount<'a, Rs>>
+}
+impl <'a, Rs: Read+Seek> Registry<'a, Rs> {
+    pub fn new() -> Registry<'a, Rs> {
+        Registry {
+            mounts: Vec::new()
+        }
+    }
+
+    /// Mount a hive so that its root key is at a registry path.
+    pub fn mount(&mut self, path: &str, hive: &'a Hive<Rs>) {
+        let components = split_path(path);
+        self.mounts.push(
+            Mount {
+                path: format!("\\{}",components.join("\\")),
+                components: components,
+                hive: hive
+            }
+        );
+    }
+
+    /// Get the key at a registry path without following links. Key paths start with the
+    /// mount path of their hive.
+    pub fn get_key(&self, path: &str)->Result<Key<'a, Rs>,RegError>{
+        self.open(path, false)
+    }
+
+    /// Get the key at a registry path, following every link key along the way, including
+    /// a link key at the end of the path. A loop of links is an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::hive::Hive;
+    /// use rwinreg::registry::Registry;
+    ///
+    /// # fn test_resolve_key() {
+    /// let file = File::open(".testdata/NTUSER.DAT").unwrap();
+    /// let hive = Hive::from_source(file).unwrap();
+    ///
+    /// let mut registry = Registry::new();
+    /// registry.mount("\\REGISTRY\\USER\\S-1-5-21", &hive);
+    ///
+    /// let key = registry.resolve_key("\\Registry\\User\\S-1-5-21\\Software\\Microsoft").unwrap();
+    /// assert_eq!(key.get_path(), "\\REGISTRY\\USER\\S-1-5-21\\Software\\Microsoft");
+    /// assert!(registry.resolve_key("\\REGISTRY\\MACHINE\\SYSTEM").is_err());
+    /// # }
+    /// # test_resolve_key();
+    /// ```
+    pub fn resolve_key(&self, path: &str)->Result<Key<'a, Rs>,RegError>{
+        self.open(path, true)
+    }
+
+    /// Follow a link key to its target, and on through any further links.
+    pub fn follow_link(&self, key: &Key<'a, Rs>)->Result<Key<'a, Rs>,RegError>{
+        match key.get_link_target()? {
+            Some(target) => self.resolve_key(&target),
+            None => {
+                Err(
+                    RegError::validation_error(
+                        format!("Key {} is not a link.", key.get_path())
+                    )
+                )
+            }
+        }
+    }
+
+    /// Find the mount with the longest path that the given path starts with.
+    fn find_mount(&self, components: &[String])->Result<(&Mount<'a, Rs>, usize),RegError>{
+        let mut found: Option<&Mount<'a, Rs>> = None;
+        for mount in self.mounts.iter() {
+            if mount.components.len() > components.len() {
+                continue;
+            }
+            let matches = mount.components.iter().zip(components.iter())
+                .all(|(mount_name, name)| utils::names_equal(mount_name, name));
+            let longer = match found {
+                Some(current) => mount.components.len() > current.components.len(),
+                None => true
+            };
+            if matches && longer {
+                found = Some(mount);
+            }
+        }
+
+        match found {
+            Some(mount) => Ok((mount, mount.components.len())),
+            None => {
+                Err(
+                    RegError::key_not_found(
+                        format!("No hive is mounted at \\{}", components.join("\\"))
+                    )
+                )
+            }
+        }
+    }
+
+    fn open(&self, path: &str, follow_links: bool)->Result<Key<'a, Rs>,RegError>{
+        // The link keys followed so far, by mount path and offset
+        let mut followed: HashSet<(String, u32)> = HashSet::new();
+        let mut components = split_path(path);
+
+        'resolve: loop {
+            let (mount, start) = self.find_mount(&components)?;
+            let mut key = Key::new(mount.hive, mount.hive.get_root_node()?, mount.path.clone());
+
+            for index in start..components.len() {
+                key = match key.subkey(&components[index])? {
+                    Some(subkey) => subkey,
+                    None => {
+                        return Err(
+                            RegError::key_not_found(
+                                format!("{} not found under {}", components[index], key.get_path())
+                            )
+                        );
+                    }
+                };
+
+                if !follow_links {
+                    continue;
+                }
+                if let Some(target) = key.get_link_target()? {
+                    if !followed.insert((mount.path.clone(), key.get_offset())) {
+                        return Err(
+                            RegError::validation_error(
+                                format!("Link key {} is part of a link loop.", key.get_path())
+                            )
+                        );
+                    }
+                    debug!("following link {} to {}",key.get_path(),target);
+
+                    // Continue with the rest of the path under the target
+                    let mut target_components = split_path(&target);
+                    target_components.extend_from_slice(&components[index + 1..]);
+                    components = target_components;
+                    continue 'resolve;
+                }
+            }
+
+            return Ok(key);
+        }
+    }
+}
+
+/// Split a registry path into its key names.
+fn split_path(path: &str)->Vec<String>{
+    path.split('\\')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder,LittleEndian};
+    use std::io::{Read,Cursor};
+    use std::fs::File;
+
+    const LINK_KEY: &'static str = "Software\\Microsoft\\Windows\\CurrentVersion\\ime\\IMTC70";
+
+    /// Turn IMTC70 into a link key by renaming its UserSymbolMapping value (at offset
+    /// 20160, with 1676 bytes of data at 20720) to a REG_LINK SymbolicLinkValue.
+    fn link_hive(target: &str)->Vec<u8>{
+        let mut buffer = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut buffer).unwrap();
+
+        let vk = 4096 + 20160 + 4;
+        buffer[vk + 20..vk + 37].copy_from_slice(b"SymbolicLinkValue");
+        LittleEndian::write_u32(&mut buffer[vk + 12..vk + 16], 6);
+        let data: Vec<u8> = target.encode_utf16()
+            .flat_map(|c| vec![c as u8, (c >> 8) as u8])
+            .collect();
+        LittleEndian::write_u32(&mut buffer[vk + 4..vk + 8], data.len() as u32);
+        let data_offset = 4096 + 20720 + 4;
+        buffer[data_offset..data_offset + data.len()].copy_from_slice(&data);
+
+        let nk = {
+            let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+            hive.get_key(LINK_KEY).unwrap().get_offset() as usize + 4096 + 4
+        };
+        buffer[nk + 2] |= 0x10;
+        buffer
+    }
+
+    #[test]
+    fn resolve_links() {
+        let buffer = link_hive("\\REGISTRY\\USER\\B\\Software\\Microsoft");
+        let hive_a = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+        let hive_b = Hive::from_source(Cursor::new(buffer)).unwrap();
+
+        let mut registry = Registry::new();
+        registry.mount("\\REGISTRY\\USER\\A", &hive_a);
+        registry.mount("\\REGISTRY\\USER\\B", &hive_b);
+
+        let link_path = format!("\\REGISTRY\\USER\\A\\{}",LINK_KEY);
+        let link = registry.get_key(&link_path).unwrap();
+        assert!(link.is_link());
+        assert_eq!(link.get_path(), link_path);
+        assert_eq!(link.get_link_target().unwrap().unwrap(), "\\REGISTRY\\USER\\B\\Software\\Microsoft");
+
+        let target = registry.follow_link(&link).unwrap();
+        assert_eq!(target.get_path(), "\\REGISTRY\\USER\\B\\Software\\Microsoft");
+
+        let key = registry.resolve_key(&format!("{}\\Windows\\CurrentVersion",link_path)).unwrap();
+        assert_eq!(key.get_path(), "\\REGISTRY\\USER\\B\\Software\\Microsoft\\Windows\\CurrentVersion");
+        assert!(registry.get_key(&format!("{}\\Windows",link_path)).is_err());
+
+        let software = registry.get_key("\\REGISTRY\\USER\\A\\Software").unwrap();
+        assert!(!software.is_link());
+        assert!(software.get_link_target().unwrap().is_none());
+        assert!(registry.follow_link(&software).is_err());
+    }
+
+    #[test]
+    fn resolve_link_loop() {
+        // The link points at itself through the ime key
+        let buffer = link_hive("\\U\\Software\\Microsoft\\Windows\\CurrentVersion\\ime\\IMTC70");
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+
+        let mut registry = Registry::new();
+        registry.mount("\\U", &hive);
+
+        let error = registry.resolve_key(&format!("\\U\\{}",LINK_KEY)).err().unwrap();
+        assert!(error.message.contains("link loop"));
+        assert!(registry.get_key(&format!("\\U\\{}",LINK_KEY)).unwrap().is_link());
+    }
+}
@@ -0,0 +1,597 @@
+use byteorder::{ByteOrder,LittleEndian};
+use errors::RegError;
+use utils;
+use serde::ser;
+use std::fmt;
+
+/// A CM_PARTIAL_RESOURCE_DESCRIPTOR is 16 bytes on 32 bit systems. On 64 bit systems
+/// the interrupt affinity is 8 bytes wide and the descriptor is 20 bytes.
+pub const PARTIAL_DESCRIPTOR_SIZE_32: usize = 16;
+pub const PARTIAL_DESCRIPTOR_SIZE_64: usize = 20;
+pub const IO_DESCRIPTOR_SIZE: usize = 32;
+
+fn check_size(buffer: &[u8], offset: usize, size: usize, name: &str)->Result<(),RegError> {
+    if offset.saturating_add(size) > buffer.len() {
+        return Err(
+            RegError::size_mismatch(
+                format!("{} at offset {} needs {} bytes, but the data is {} bytes.",
+                    name, offset, size, buffer.len()
+                )
+            )
+        );
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct InterfaceType(i32);
+impl InterfaceType {
+    pub fn new(value: i32) -> InterfaceType {
+        InterfaceType(value)
+    }
+
+    pub fn as_string(&self)->String{
+        match self.0 {
+            -1 => "InterfaceTypeUndefined".to_string(),
+            0 => "Internal".to_string(),
+            1 => "Isa".to_string(),
+            2 => "Eisa".to_string(),
+            3 => "MicroChannel".to_string(),
+            4 => "TurboChannel".to_string(),
+            5 => "PCIBus".to_string(),
+            6 => "VMEBus".to_string(),
+            7 => "NuBus".to_string(),
+            8 => "PCMCIABus".to_string(),
+            9 => "CBus".to_string(),
+            10 => "MPIBus".to_string(),
+            11 => "MPSABus".to_string(),
+            12 => "ProcessorInternal".to_string(),
+            13 => "InternalPowerBus".to_string(),
+            14 => "PNPISABus".to_string(),
+            15 => "PNPBus".to_string(),
+            16 => "Vmcs".to_string(),
+            17 => "ACPIBus".to_string(),
+            _ => format!("INTERFACE_TYPE: {}",self.0)
+        }
+    }
+
+    pub fn as_i32(&self)->i32{
+        self.0
+    }
+}
+impl fmt::Debug for InterfaceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}",self.as_string())
+    }
+}
+impl ser::Serialize for InterfaceType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&self.as_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct ResourceType(u8);
+impl ResourceType {
+    pub fn new(value: u8) -> ResourceType {
+        ResourceType(value)
+    }
+
+    pub fn as_string(&self)->String{
+        match self.0 {
+            0 => "CmResourceTypeNull".to_string(),
+            1 => "CmResourceTypePort".to_string(),
+            2 => "CmResourceTypeInterrupt".to_string(),
+            3 => "CmResourceTypeMemory".to_string(),
+            4 => "CmResourceTypeDma".to_string(),
+            5 => "CmResourceTypeDeviceSpecific".to_string(),
+            6 => "CmResourceTypeBusNumber".to_string(),
+            7 => "CmResourceTypeMemoryLarge".to_string(),
+            128 => "CmResourceTypeConfigData".to_string(),
+            129 => "CmResourceTypeDevicePrivate".to_string(),
+            130 => "CmResourceTypePcCardConfig".to_string(),
+            131 => "CmResourceTypeMfCardConfig".to_string(),
+            132 => "CmResourceTypeConnection".to_string(),
+            _ => format!("CM_RESOURCE_TYPE: {}",self.0)
+        }
+    }
+
+    pub fn as_u8(&self)->u8{
+        self.0
+    }
+}
+impl fmt::Debug for ResourceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}",self.as_string())
+    }
+}
+impl ser::Serialize for ResourceType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_str(&self.as_string())
+    }
+}
+
+/// The resource assigned by a partial descriptor.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Resource {
+    Null,
+    Port { start: u64, length: u32 },
+    Interrupt { level: u16, group: u16, vector: u32, affinity: u64 },
+    /// Memory and large memory ranges, with the length already scaled
+    Memory { start: u64, length: u64 },
+    Dma { channel: u32, port: u32 },
+    BusNumber { start: u32, length: u32 },
+    /// Device specific data follows the descriptor
+    DeviceSpecific { data: String },
+    Other { data: String }
+}
+
+// CM_PARTIAL_RESOURCE_DESCRIPTOR
+#[derive(Serialize, Debug, Clone)]
+pub struct PartialDescriptor {
+    resource_type: ResourceType,
+    share_disposition: u8,
+    flags: u16,
+    resource: Resource
+}
+impl PartialDescriptor {
+    /// Read a partial descriptor. Returns the descriptor and the offset of the next one.
+    pub fn new(buffer: &[u8], offset: usize, descriptor_size: usize) -> Result<(PartialDescriptor,usize),RegError> {
+        check_size(buffer, offset, descriptor_size, "Partial resource descriptor")?;
+        let mut next_offset = offset + descriptor_size;
+
+        let resource_type = ResourceType::new(buffer[offset]);
+        let share_disposition = buffer[offset + 1];
+        let flags = LittleEndian::read_u16(&buffer[offset + 2..offset + 4]);
+        let u = &buffer[offset + 4..offset + descriptor_size];
+
+        let resource = match resource_type.as_u8() {
+            0 => Resource::Null,
+            1 => {
+                Resource::Port {
+                    start: LittleEndian::read_u64(&u[0..8]),
+                    length: LittleEndian::read_u32(&u[8..12])
+                }
+            },
+            2 => {
+                let affinity = match descriptor_size {
+                    PARTIAL_DESCRIPTOR_SIZE_64 => LittleEndian::read_u64(&u[8..16]),
+                    _ => LittleEndian::read_u32(&u[8..12]) as u64
+                };
+                Resource::Interrupt {
+                    level: LittleEndian::read_u16(&u[0..2]),
+                    group: LittleEndian::read_u16(&u[2..4]),
+                    vector: LittleEndian::read_u32(&u[4..8]),
+                    affinity: affinity
+                }
+            },
+            3 => {
+                Resource::Memory {
+                    start: LittleEndian::read_u64(&u[0..8]),
+                    length: LittleEndian::read_u32(&u[8..12]) as u64
+                }
+            },
+            4 => {
+                Resource::Dma {
+                    channel: LittleEndian::read_u32(&u[0..4]),
+                    port: LittleEndian::read_u32(&u[4..8])
+                }
+            },
+            5 => {
+                let data_size = LittleEndian::read_u32(&u[0..4]) as usize;
+                check_size(buffer, next_offset, data_size, "Device specific data")?;
+                let data = utils::to_hex_string(&buffer[next_offset..next_offset + data_size]);
+                next_offset += data_size;
+                Resource::DeviceSpecific {
+                    data: data
+                }
+            },
+            6 => {
+                Resource::BusNumber {
+                    start: LittleEndian::read_u32(&u[0..4]),
+                    length: LittleEndian::read_u32(&u[4..8])
+                }
+            },
+            7 => {
+                // CM_RESOURCE_MEMORY_LARGE_40, _48 and _64
+                let shift = match flags & 0x0E00 {
+                    0x0200 => 8,
+                    0x0400 => 16,
+                    0x0800 => 32,
+                    _ => 0
+                };
+                Resource::Memory {
+                    start: LittleEndian::read_u64(&u[0..8]),
+                    length: (LittleEndian::read_u32(&u[8..12]) as u64) << shift
+                }
+            },
+            _ => {
+                Resource::Other {
+                    data: utils::to_hex_string(u)
+                }
+            }
+        };
+
+        Ok(
+            (
+                PartialDescriptor {
+                    resource_type: resource_type,
+                    share_disposition: share_disposition,
+                    flags: flags,
+                    resource: resource
+                },
+                next_offset
+            )
+        )
+    }
+
+    pub fn get_resource_type(&self)->&ResourceType{
+        &self.resource_type
+    }
+
+    pub fn get_resource(&self)->&Resource{
+        &self.resource
+    }
+}
+
+// CM_FULL_RESOURCE_DESCRIPTOR
+#[derive(Serialize, Debug, Clone)]
+pub struct FullResourceDescriptor {
+    interface_type: InterfaceType,
+    bus_number: u32,
+    version: u16,
+    revision: u16,
+    descriptors: Vec<PartialDescriptor>
+}
+impl FullResourceDescriptor {
+    /// Read a full resource descriptor. Returns the descriptor and the offset of the next one.
+    pub fn new(buffer: &[u8], offset: usize, descriptor_size: usize) -> Result<(FullResourceDescriptor,usize),RegError> {
+        check_size(buffer, offset, 16, "Full resource descriptor")?;
+
+        let interface_type = InterfaceType::new(
+            LittleEndian::read_i32(&buffer[offset..offset + 4])
+        );
+        let bus_number = LittleEndian::read_u32(&buffer[offset + 4..offset + 8]);
+        let version = LittleEndian::read_u16(&buffer[offset + 8..offset + 10]);
+        let revision = LittleEndian::read_u16(&buffer[offset + 10..offset + 12]);
+        let count = LittleEndian::read_u32(&buffer[offset + 12..offset + 16]) as usize;
+
+        let mut next_offset = offset + 16;
+        check_size(buffer, next_offset, count.saturating_mul(descriptor_size), "Partial resource list")?;
+
+        let mut descriptors = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (descriptor, descriptor_end) = PartialDescriptor::new(buffer, next_offset, descriptor_size)?;
+            descriptors.push(descriptor);
+            next_offset = descriptor_end;
+        }
+
+        Ok(
+            (
+                FullResourceDescriptor {
+                    interface_type: interface_type,
+                    bus_number: bus_number,
+                    version: version,
+                    revision: revision,
+                    descriptors: descriptors
+                },
+                next_offset
+            )
+        )
+    }
+
+    pub fn get_interface_type(&self)->&InterfaceType{
+        &self.interface_type
+    }
+
+    pub fn get_bus_number(&self)->u32{
+        self.bus_number
+    }
+
+    pub fn get_descriptors(&self)->&Vec<PartialDescriptor>{
+        &self.descriptors
+    }
+}
+
+/// Parse with the 64 bit partial descriptor size, falling back to the 32 bit size when
+/// the 64 bit layout does not account for the data exactly.
+fn with_descriptor_size<T, F>(buffer: &[u8], parse: F)->Result<T,RegError>
+    where F: Fn(&[u8], usize)->Result<(T,usize),RegError>
+{
+    match parse(buffer, PARTIAL_DESCRIPTOR_SIZE_64) {
+        Ok((value, end)) => {
+            if end == buffer.len() {
+                return Ok(value);
+            }
+            match parse(buffer, PARTIAL_DESCRIPTOR_SIZE_32) {
+                Ok((narrow_value, narrow_end)) if narrow_end == buffer.len() => Ok(narrow_value),
+                _ => Ok(value)
+            }
+        },
+        Err(_) => {
+            parse(buffer, PARTIAL_DESCRIPTOR_SIZE_32).map(|(value, _)| value)
+        }
+    }
+}
+
+/// Decode REG_FULL_RESOURCE_DESCRIPTOR data.
+pub fn read_full_resource_descriptor(buffer: &[u8])->Result<FullResourceDescriptor,RegError> {
+    with_descriptor_size(buffer, |buffer, descriptor_size| {
+        FullResourceDescriptor::new(buffer, 0, descriptor_size)
+    })
+}
+
+/// Decode REG_RESOURCE_LIST data (a CM_RESOURCE_LIST).
+pub fn read_resource_list(buffer: &[u8])->Result<Vec<FullResourceDescriptor>,RegError> {
+    with_descriptor_size(buffer, |buffer, descriptor_size| {
+        check_size(buffer, 0, 4, "Resource list")?;
+        let count = LittleEndian::read_u32(&buffer[0..4]) as usize;
+        check_size(buffer, 4, count.saturating_mul(16), "Resource list")?;
+
+        let mut next_offset = 4;
+        let mut lists = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (list, list_end) = FullResourceDescriptor::new(buffer, next_offset, descriptor_size)?;
+            lists.push(list);
+            next_offset = list_end;
+        }
+        Ok((lists, next_offset))
+    })
+}
+
+/// The range of resources an IO resource descriptor will accept.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum IoResource {
+    /// Port, memory and large memory ranges
+    Range { length: u32, alignment: u32, minimum_address: u64, maximum_address: u64 },
+    Interrupt { minimum_vector: u32, maximum_vector: u32 },
+    Dma { minimum_channel: u32, maximum_channel: u32 },
+    BusNumber { length: u32, minimum_bus_number: u32, maximum_bus_number: u32 },
+    Other { data: String }
+}
+
+// IO_RESOURCE_DESCRIPTOR
+#[derive(Serialize, Debug, Clone)]
+pub struct IoResourceDescriptor {
+    option: u8,
+    resource_type: ResourceType,
+    share_disposition: u8,
+    flags: u16,
+    resource: IoResource
+}
+impl IoResourceDescriptor {
+    pub fn new(buffer: &[u8], offset: usize) -> Result<IoResourceDescriptor,RegError> {
+        check_size(buffer, offset, IO_DESCRIPTOR_SIZE, "IO resource descriptor")?;
+
+        let option = buffer[offset];
+        let resource_type = ResourceType::new(buffer[offset + 1]);
+        let share_disposition = buffer[offset + 2];
+        let flags = LittleEndian::read_u16(&buffer[offset + 4..offset + 6]);
+        let u = &buffer[offset + 8..offset + IO_DESCRIPTOR_SIZE];
+
+        let resource = match resource_type.as_u8() {
+            1 | 3 | 7 => {
+                IoResource::Range {
+                    length: LittleEndian::read_u32(&u[0..4]),
+                    alignment: LittleEndian::read_u32(&u[4..8]),
+                    minimum_address: LittleEndian::read_u64(&u[8..16]),
+                    maximum_address: LittleEndian::read_u64(&u[16..24])
+                }
+            },
+            2 => {
+                IoResource::Interrupt {
+                    minimum_vector: LittleEndian::read_u32(&u[0..4]),
+                    maximum_vector: LittleEndian::read_u32(&u[4..8])
+                }
+            },
+            4 => {
+                IoResource::Dma {
+                    minimum_channel: LittleEndian::read_u32(&u[0..4]),
+                    maximum_channel: LittleEndian::read_u32(&u[4..8])
+                }
+            },
+            6 => {
+                IoResource::BusNumber {
+                    length: LittleEndian::read_u32(&u[0..4]),
+                    minimum_bus_number: LittleEndian::read_u32(&u[4..8]),
+                    maximum_bus_number: LittleEndian::read_u32(&u[8..12])
+                }
+            },
+            _ => {
+                IoResource::Other {
+                    data: utils::to_hex_string(u)
+                }
+            }
+        };
+
+        Ok(
+            IoResourceDescriptor {
+                option: option,
+                resource_type: resource_type,
+                share_disposition: share_disposition,
+                flags: flags,
+                resource: resource
+            }
+        )
+    }
+
+    pub fn get_resource_type(&self)->&ResourceType{
+        &self.resource_type
+    }
+
+    pub fn get_resource(&self)->&IoResource{
+        &self.resource
+    }
+}
+
+// IO_RESOURCE_LIST
+#[derive(Serialize, Debug, Clone)]
+pub struct IoResourceList {
+    version: u16,
+    revision: u16,
+    descriptors: Vec<IoResourceDescriptor>
+}
+
+// IO_RESOURCE_REQUIREMENTS_LIST
+#[derive(Serialize, Debug, Clone)]
+pub struct ResourceRequirementsList {
+    interface_type: InterfaceType,
+    bus_number: u32,
+    slot_number: u32,
+    alternative_lists: Vec<IoResourceList>
+}
+impl ResourceRequirementsList {
+    /// Decode REG_RESOURCE_REQUIREMENTS_LIST data.
+    pub fn new(buffer: &[u8]) -> Result<ResourceRequirementsList,RegError> {
+        check_size(buffer, 0, 32, "Resource requirements list")?;
+
+        let interface_type = InterfaceType::new(
+            LittleEndian::read_i32(&buffer[4..8])
+        );
+        let bus_number = LittleEndian::read_u32(&buffer[8..12]);
+        let slot_number = LittleEndian::read_u32(&buffer[12..16]);
+        let list_count = LittleEndian::read_u32(&buffer[28..32]) as usize;
+        check_size(buffer, 32, list_count.saturating_mul(8), "Alternative resource lists")?;
+
+        let mut offset = 32;
+        let mut alternative_lists = Vec::with_capacity(list_count);
+        for _ in 0..list_count {
+            check_size(buffer, offset, 8, "IO resource list")?;
+            let version = LittleEndian::read_u16(&buffer[offset..offset + 2]);
+            let revision = LittleEndian::read_u16(&buffer[offset + 2..offset + 4]);
+            let count = LittleEndian::read_u32(&buffer[offset + 4..offset + 8]) as usize;
+            offset += 8;
+            check_size(buffer, offset, count.saturating_mul(IO_DESCRIPTOR_SIZE), "IO resource list")?;
+
+            let mut descriptors = Vec::with_capacity(count);
+            for _ in 0..count {
+                descriptors.push(
+                    IoResourceDescriptor::new(buffer, offset)?
+                );
+                offset += IO_DESCRIPTOR_SIZE;
+            }
+
+            alternative_lists.push(
+                IoResourceList {
+                    version: version,
+                    revision: revision,
+                    descriptors: descriptors
+                }
+            );
+        }
+
+        Ok(
+            ResourceRequirementsList {
+                interface_type: interface_type,
+                bus_number: bus_number,
+                slot_number: slot_number,
+                alternative_lists: alternative_lists
+            }
+        )
+    }
+
+    pub fn get_interface_type(&self)->&InterfaceType{
+        &self.interface_type
+    }
+
+    pub fn get_alternative_lists(&self)->&Vec<IoResourceList>{
+        &self.alternative_lists
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    // A COM port on a PCI bus: an IO port, an interrupt and a memory range
+    fn resource_list(descriptor_size: usize)->Vec<u8> {
+        let mut buffer = vec![0; 4 + 16 + 3 * descriptor_size];
+        LittleEndian::write_u32(&mut buffer[0..4], 1);
+        LittleEndian::write_i32(&mut buffer[4..8], 5);
+        LittleEndian::write_u16(&mut buffer[12..14], 1);
+        LittleEndian::write_u16(&mut buffer[14..16], 1);
+        LittleEndian::write_u32(&mut buffer[16..20], 3);
+
+        let port = 20;
+        buffer[port] = 1;
+        buffer[port + 1] = 1;
+        LittleEndian::write_u16(&mut buffer[port + 2..port + 4], 0x0005);
+        LittleEndian::write_u64(&mut buffer[port + 4..port + 12], 0x3F8);
+        LittleEndian::write_u32(&mut buffer[port + 12..port + 16], 8);
+
+        let interrupt = port + descriptor_size;
+        buffer[interrupt] = 2;
+        LittleEndian::write_u16(&mut buffer[interrupt + 4..interrupt + 6], 4);
+        LittleEndian::write_u32(&mut buffer[interrupt + 8..interrupt + 12], 4);
+        LittleEndian::write_u32(&mut buffer[interrupt + 12..interrupt + 16], 0xFFFFFFFF);
+
+        let memory = interrupt + descriptor_size;
+        buffer[memory] = 3;
+        LittleEndian::write_u64(&mut buffer[memory + 4..memory + 12], 0xFEBC0000);
+        LittleEndian::write_u32(&mut buffer[memory + 12..memory + 16], 0x1000);
+
+        buffer
+    }
+
+    #[test]
+    fn resource_list_descriptor_sizes() {
+        for descriptor_size in [PARTIAL_DESCRIPTOR_SIZE_32, PARTIAL_DESCRIPTOR_SIZE_64].iter() {
+            let lists = read_resource_list(&resource_list(*descriptor_size)).unwrap();
+            assert_eq!(lists.len(), 1);
+            assert_eq!(lists[0].get_interface_type().as_string(), "PCIBus");
+
+            let descriptors = lists[0].get_descriptors();
+            assert_eq!(descriptors.len(), 3);
+            match *descriptors[1].get_resource() {
+                Resource::Interrupt { level: 4, vector: 4, affinity: 0xFFFFFFFF, .. } => {},
+                ref other => panic!("{:?}",other)
+            }
+            match *descriptors[2].get_resource() {
+                Resource::Memory { start: 0xFEBC0000, length: 0x1000 } => {},
+                ref other => panic!("{:?}",other)
+            }
+
+            let json = serde_json::to_string(&descriptors[0]).unwrap();
+            assert_eq!(json, r#"{"resource_type":"CmResourceTypePort","share_disposition":1,"flags":5,"resource":{"start":1016,"length":8}}"#);
+        }
+    }
+
+    #[test]
+    fn resource_list_short_buffer() {
+        let buffer = resource_list(PARTIAL_DESCRIPTOR_SIZE_32);
+        assert!(read_resource_list(&buffer[0..buffer.len() - 1]).is_err());
+
+        let descriptor = read_full_resource_descriptor(&buffer[4..]).unwrap();
+        assert_eq!(descriptor.get_descriptors().len(), 3);
+    }
+
+    #[test]
+    fn resource_requirements_list() {
+        let mut buffer = vec![0; 32 + 8 + IO_DESCRIPTOR_SIZE];
+        LittleEndian::write_i32(&mut buffer[4..8], 15);
+        LittleEndian::write_u32(&mut buffer[28..32], 1);
+        LittleEndian::write_u32(&mut buffer[36..40], 1);
+        buffer[41] = 2;
+        LittleEndian::write_u32(&mut buffer[48..52], 1);
+        LittleEndian::write_u32(&mut buffer[52..56], 15);
+
+        let list = ResourceRequirementsList::new(&buffer).unwrap();
+        assert_eq!(list.get_interface_type().as_string(), "PNPBus");
+        let descriptor = &list.get_alternative_lists()[0].descriptors[0];
+        assert_eq!(descriptor.get_resource_type().as_string(), "CmResourceTypeInterrupt");
+        match *descriptor.get_resource() {
+            IoResource::Interrupt { minimum_vector: 1, maximum_vector: 15 } => {},
+            ref other => panic!("{:?}",other)
+        }
+
+        LittleEndian::write_u32(&mut buffer[36..40], 2);
+        assert!(ResourceRequirementsList::new(&buffer).is_err());
+    }
+}
@@ -1,10 +1,12 @@
 use byteorder::{ByteOrder,LittleEndian};
 use errors::RegError;
+use utils;
 use hive::HBIN_START_OFFSET;
 use cell::Cell;
 use cell::CellData;
 use nk::NodeKey;
 use std::io::{Read,Seek};
+use std::cmp::Ordering;
 
 // ri
 #[derive(Serialize, Debug)]
@@ -19,14 +21,25 @@ pub struct RootIndex{
 }
 impl RootIndex{
     pub fn new(buffer: &[u8], offset: u64) -> Result<RootIndex,RegError> {
+        utils::check_len(buffer, 4, "RootIndex", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let element_count = LittleEndian::read_u16(&buffer[2..4]);
+        if buffer.len() < 4 + element_count as usize * 4 {
+            return Err(
+                RegError::validation_error(
+                    format!("RootIndex at offset {} has {} elements but only {} bytes.",
+                        offset, element_count, buffer.len()
+                    )
+                )
+            );
+        }
         let mut elements: Vec<u32> = Vec::new();
         let next_index: usize = 0;
         let current_cell_data = None;
 
-        for i in 0..element_count {
-            let o = (4 + (i*4)) as usize;
+        for i in 0..element_count as usize {
+            let o = 4 + i*4;
             let element = LittleEndian::read_u32(&buffer[o..o+4]);
             elements.push(element);
         }
@@ -43,6 +56,52 @@ impl RootIndex{
         )
     }
 
+    /// The offsets of the subkey lists in this index.
+    pub fn get_offsets(&self)->Vec<u32>{
+        self.elements.clone()
+    }
+
+    /// Find a node key by name. The sub lists are sorted, so a binary search on the last
+    /// key of each sub list picks the only list that can hold the name.
+    pub fn find_key<Rs: Read+Seek>(&self, reader: &mut Rs, name: &str)->Result<Option<NodeKey>,RegError>{
+        let mut low = 0;
+        let mut high = self.elements.len();
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let last_offset = match read_sub_list(reader, self.elements[middle])?.last() {
+                Some(offset) => *offset,
+                None => {
+                    low = middle + 1;
+                    continue;
+                }
+            };
+
+            let nk = NodeKey::at_offset(reader, last_offset)?;
+            match utils::compare_names(nk.key_name(), name) {
+                Ordering::Equal => return Ok(Some(nk)),
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle
+            }
+        }
+
+        if low >= self.elements.len() {
+            return Ok(None);
+        }
+
+        let list_offset = self.elements[low];
+        let cell = Cell::at_offset(reader, list_offset as u64 + HBIN_START_OFFSET)?;
+        match cell.get_data()? {
+            CellData::FastLeaf(lf) => lf.find_key(reader, name),
+            CellData::HashLeaf(lh) => lh.find_key(reader, name),
+            CellData::IndexLeaf(li) => li.find_key(reader, name),
+            other => {
+                Err(
+                    RegError::unexpected_cell_type(cell.get_offset(), "lf, lh or li", other.get_type_name())
+                )
+            }
+        }
+    }
+
     pub fn increment_current_cell_data<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<bool,RegError>{
         if self.next_index + 1 > self.elements.len() {
             return Ok(false);
@@ -51,6 +110,8 @@ impl RootIndex{
         // Get the cell offset for a node list
         let cell_offset = self.elements[self.next_index] as u64 + HBIN_START_OFFSET;
 
+        self.next_index += 1;
+
         let cell = Cell::at_offset(
             reader, cell_offset
         )?;
@@ -60,66 +121,71 @@ impl RootIndex{
             Box::new(cell.get_data()?)
         );
 
-        self.next_index += 1;
-
         Ok(true)
     }
 
     pub fn get_next_key<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<NodeKey>,RegError>{
         loop {
-            if self.next_index + 1 > self.elements.len() {
-                // No more lists to iterate through
-                return Ok(None);
-            }
-
             // Check if we need to set the current cell
             if self.current_cell_data.is_none(){
                 match self.increment_current_cell_data(reader)? {
                     false => {
-                        // No more indexes
-                        break;
+                        // No more lists to iterate through
+                        return Ok(None);
                     },
                     true => {}
                 }
             }
 
-            let mut get_next_cell_flag = false;
-            match self.current_cell_data {
+            let result = match self.current_cell_data {
                 Some(ref mut cell_data) => {
                     match **cell_data {
-                        CellData::HashLeaf(ref mut hl) => {
-                            let nk_option = hl.get_next_key(reader)?;
-                            match nk_option {
-                                Some(nk) => {
-                                    return Ok(Some(nk));
-                                },
-                                None => {
-                                    get_next_cell_flag = true
-                                }
-                            }
-                        },
-                        _ => {
-                            panic!("Unhandled cell data type: {:?}",cell_data);
+                        CellData::FastLeaf(ref mut lf) => lf.get_next_key(reader),
+                        CellData::HashLeaf(ref mut lh) => lh.get_next_key(reader),
+                        CellData::IndexLeaf(ref mut li) => li.get_next_key(reader),
+                        ref other => {
+                            Err(
+                                RegError::unexpected_cell_type(
+                                    self.elements[self.next_index - 1] as u64 + HBIN_START_OFFSET,
+                                    "lf, lh or li",
+                                    other.get_type_name()
+                                )
+                            )
                         }
                     }
-                }
-                None => {}
-            }
+                },
+                None => Ok(None)
+            };
 
-            if get_next_cell_flag {
-                // No more nodes in the current list, lets go to the next list in the ri
-                match self.increment_current_cell_data(reader)? {
-                    false => {
-                        // No more indexes
-                        return Ok(None);
-                    },
-                    true => {
-                        continue;
-                    }
+            match result {
+                Ok(Some(nk)) => {
+                    return Ok(Some(nk));
+                },
+                Ok(None) => {
+                    // No more nodes in the current list, lets go to the next list in the ri
+                    self.current_cell_data = None;
+                },
+                Err(error) => {
+                    // Move on to the next list when called again
+                    self.current_cell_data = None;
+                    return Err(error);
                 }
             }
         }
+    }
+}
 
-        Ok(None)
+/// Read the node key offsets of a sub list referenced by a root index.
+fn read_sub_list<Rs: Read+Seek>(reader: &mut Rs, list_offset: u32)->Result<Vec<u32>,RegError>{
+    let cell = Cell::at_offset(reader, list_offset as u64 + HBIN_START_OFFSET)?;
+    match cell.get_data()? {
+        CellData::FastLeaf(lf) => Ok(lf.get_offsets()),
+        CellData::HashLeaf(lh) => Ok(lh.get_offsets()),
+        CellData::IndexLeaf(li) => Ok(li.get_offsets()),
+        other => {
+            Err(
+                RegError::unexpected_cell_type(cell.get_offset(), "lf, lh or li", other.get_type_name())
+            )
+        }
     }
 }
@@ -1,6 +1,11 @@
 use rwinstructs::security::{SecurityDescriptor};
 use byteorder::{ByteOrder,LittleEndian};
+use utils;
 use errors::{RegError};
+use hive::HBIN_START_OFFSET;
+use cell::{Cell,CellData};
+use std::collections::HashMap;
+use std::io::{Read,Seek};
 use std::io::Cursor;
 
 #[derive(Serialize, Debug, Clone)]
@@ -16,15 +21,26 @@ pub struct SecurityKey {
 }
 impl SecurityKey {
     pub fn new(buffer: &[u8], offset: u64) -> Result<SecurityKey,RegError> {
+        utils::check_len(buffer, 20, "SecurityKey", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let unknown1 = LittleEndian::read_u16(&buffer[2..4]);
         let previous_sec_key_offset = LittleEndian::read_u32(&buffer[4..8]);
         let next_sec_key_offset = LittleEndian::read_u32(&buffer[8..12]);
         let reference_count = LittleEndian::read_u32(&buffer[12..16]);
         let descriptor_size = LittleEndian::read_u32(&buffer[16..20]);
+        if buffer.len() - 20 < descriptor_size as usize {
+            return Err(
+                RegError::size_mismatch(
+                    format!("SecurityKey at offset {} has a descriptor size of {} but only {} bytes.",
+                        offset, descriptor_size, buffer.len()
+                    )
+                )
+            );
+        }
 
         let descriptor = SecurityDescriptor::new(
-            Cursor::new(&buffer[20..])
+            Cursor::new(&buffer[20..20 + descriptor_size as usize])
         )?;
 
         Ok(
@@ -41,9 +57,192 @@ impl SecurityKey {
         )
     }
 
+    /// Read the security key at an offset relative to the first hive bin.
+    pub fn at_offset<Rs: Read+Seek>(reader: &mut Rs, offset: u32)->Result<SecurityKey,RegError>{
+        let cell = Cell::at_offset(
+            reader,
+            offset as u64 + HBIN_START_OFFSET
+        )?;
+        match cell.get_data()? {
+            CellData::SecurityKey(sk) => Ok(sk),
+            other => {
+                Err(
+                    RegError::unexpected_cell_type(cell.get_offset(), "sk", other.get_type_name())
+                )
+            }
+        }
+    }
+
     pub fn get_descriptor(&self)->&SecurityDescriptor {
         &self.descriptor
     }
+
+    pub fn get_previous_offset(&self)->u32 {
+        self.previous_sec_key_offset
+    }
+
+    pub fn get_next_offset(&self)->u32 {
+        self.next_sec_key_offset
+    }
+
+    /// The number of keys that use this security key.
+    pub fn get_reference_count(&self)->u32 {
+        self.reference_count
+    }
+}
+
+/// A security key whose reference count is not the number of keys pointing at it.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ReferenceCountMismatch {
+    pub offset: u32,
+    pub reference_count: u32,
+    pub key_count: u32
+}
+
+/// A key that points at a security key that is not on the security key list.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UnlistedKey {
+    pub path: String,
+    pub security_id: u32
+}
+
+/// The security keys of a hive, read once by walking the doubly linked list (a ring)
+/// that joins them and cached by cell offset. The offset of a security key identifies
+/// its descriptor, so records can refer to it instead of carrying a copy.
+#[derive(Debug)]
+pub struct SecurityKeyList {
+    offsets: Vec<u32>,
+    keys: HashMap<u32,SecurityKey>,
+    key_counts: HashMap<u32,u32>,
+    unlisted_keys: Vec<UnlistedKey>
+}
+impl SecurityKeyList {
+    /// Walk the list from the security key at an offset relative to the first hive bin
+    /// until it comes back around. A link that does not point back, or a walk that loops
+    /// without returning to the start, is an error.
+    pub fn read<Rs: Read+Seek>(reader: &mut Rs, start_offset: u32)->Result<SecurityKeyList,RegError>{
+        let mut offsets = Vec::new();
+        let mut keys = HashMap::new();
+
+        let mut offset = start_offset;
+        loop {
+            let sk = SecurityKey::at_offset(reader, offset)?;
+            if let Some(&previous_offset) = offsets.last() {
+                if sk.get_previous_offset() != previous_offset {
+                    return Err(
+                        RegError::validation_error(
+                            format!("SecurityKey at offset {} has a previous offset of {} but follows the security key at offset {}.",
+                                offset, sk.get_previous_offset(), previous_offset
+                            )
+                        )
+                    );
+                }
+            }
+
+            let next_offset = sk.get_next_offset();
+            offsets.push(offset);
+            keys.insert(offset, sk);
+
+            if next_offset == start_offset {
+                break;
+            }
+            if keys.contains_key(&next_offset) {
+                return Err(
+                    RegError::validation_error(
+                        format!("SecurityKey at offset {} links back to offset {} instead of the first security key at offset {}.",
+                            offset, next_offset, start_offset
+                        )
+                    )
+                );
+            }
+            offset = next_offset;
+        }
+
+        let last_offset = offsets[offsets.len() - 1];
+        if keys[&start_offset].get_previous_offset() != last_offset {
+            return Err(
+                RegError::validation_error(
+                    format!("SecurityKey at offset {} has a previous offset of {} but the last security key is at offset {}.",
+                        start_offset, keys[&start_offset].get_previous_offset(), last_offset
+                    )
+                )
+            );
+        }
+
+        Ok(
+            SecurityKeyList {
+                offsets: offsets,
+                keys: keys,
+                key_counts: HashMap::new(),
+                unlisted_keys: Vec::new()
+            }
+        )
+    }
+
+    /// Count a key that points at the security key at an offset. Returns false, and keeps
+    /// the key as unlisted, when the offset is not on the list.
+    pub fn count_key(&mut self, path: &str, offset: u32)->bool {
+        if !self.keys.contains_key(&offset) {
+            self.unlisted_keys.push(
+                UnlistedKey {
+                    path: path.to_string(),
+                    security_id: offset
+                }
+            );
+            return false;
+        }
+        *self.key_counts.entry(offset).or_insert(0) += 1;
+        true
+    }
+
+    /// The offsets of the security keys in list order.
+    pub fn get_offsets(&self)->&Vec<u32> {
+        &self.offsets
+    }
+
+    pub fn len(&self)->usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self)->bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn get(&self, offset: u32)->Option<&SecurityKey> {
+        self.keys.get(&offset)
+    }
+
+    pub fn get_descriptor(&self, offset: u32)->Option<&SecurityDescriptor> {
+        self.keys.get(&offset).map(|sk| sk.get_descriptor())
+    }
+
+    /// The number of keys counted as pointing at the security key at an offset.
+    pub fn get_key_count(&self, offset: u32)->u32 {
+        match self.key_counts.get(&offset) {
+            Some(&count) => count,
+            None => 0
+        }
+    }
+
+    /// The keys counted that point at a security key that is not on the list.
+    pub fn unlisted_keys(&self)->&Vec<UnlistedKey> {
+        &self.unlisted_keys
+    }
+
+    /// The security keys, in list order, whose reference count differs from the number
+    /// of keys counted.
+    pub fn reference_count_mismatches(&self)->Vec<ReferenceCountMismatch> {
+        self.offsets.iter()
+            .filter(|&&offset| self.keys[&offset].get_reference_count() != self.get_key_count(offset))
+            .map(|&offset| {
+                ReferenceCountMismatch {
+                    offset: offset,
+                    reference_count: self.keys[&offset].get_reference_count(),
+                    key_count: self.get_key_count(offset)
+                }
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -81,4 +280,49 @@ mod tests {
         assert_eq!(sk.reference_count, 84);
         assert_eq!(sk.descriptor_size, 160);
     }
+
+    #[test]
+    fn securitykey_descriptor_size() {
+        let mut file = File::open(".testdata/NTUSER_4768_184_CELL_SK.DAT").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+
+        // A descriptor size past the end of the cell
+        LittleEndian::write_u32(&mut buffer[20..24], 4096);
+        let cell = Cell::new(&mut Cursor::new(&buffer),0).unwrap();
+        assert!(SecurityKey::new(&cell.data,4).is_err());
+    }
+
+    #[test]
+    fn security_key_list() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+
+        // The security key of the root key
+        let mut security_keys = SecurityKeyList::read(&mut Cursor::new(&buffer), 7568).unwrap();
+        assert_eq!(security_keys.len(), 116);
+        assert_eq!(&security_keys.get_offsets()[0..3], &[7568, 129608, 23920]);
+        assert_eq!(security_keys.get(672).unwrap().get_reference_count(), 84);
+        assert!(security_keys.get_descriptor(7568).is_some());
+        assert!(security_keys.get(4680 - 4096).is_none());
+
+        assert!(security_keys.count_key("\\ROOT", 129608));
+        assert!(!security_keys.count_key("\\ROOT\\Key", 4680 - 4096));
+        assert_eq!(security_keys.get_key_count(129608), 1);
+        assert_eq!(security_keys.unlisted_keys(), &vec![UnlistedKey { path: "\\ROOT\\Key".to_string(), security_id: 4680 - 4096 }]);
+        let mismatches = security_keys.reference_count_mismatches();
+        assert_eq!(mismatches.len(), 116);
+        assert_eq!(mismatches[1], ReferenceCountMismatch { offset: 129608, reference_count: 3, key_count: 1 });
+
+        // The second security key no longer points back at the first
+        let mut broken = buffer.clone();
+        LittleEndian::write_u32(&mut broken[4096 + 129608 + 8..4096 + 129608 + 12], 672);
+        assert!(SecurityKeyList::read(&mut Cursor::new(&broken), 7568).is_err());
+
+        // The second security key links back to itself
+        let mut broken = buffer.clone();
+        LittleEndian::write_u32(&mut broken[4096 + 129608 + 12..4096 + 129608 + 16], 129608);
+        assert!(SecurityKeyList::read(&mut Cursor::new(&broken), 7568).is_err());
+    }
 }
@@ -0,0 +1,171 @@
+use cell::CellData;
+use cell::CellIterator;
+use vk::ValueKey;
+use baseblock::HiveVersion;
+use utils;
+use serde::ser::{SerializeStruct};
+use serde::ser;
+use std::io::{Read,Seek};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SlackSource {
+    /// Bytes after the key name of a nk cell
+    NodeKey,
+    /// Bytes after the value name of a vk cell
+    ValueKey,
+    /// Bytes after the value data of a data cell
+    ValueData
+}
+
+/// Bytes between the logical end of a structure and the end of its cell.
+#[derive(Debug)]
+pub struct Slack {
+    /// Absolute offset of the cell that holds the slack (the last segment cell for big data)
+    pub cell_offset: u64,
+    /// Absolute offset of the key or value cell the slack belongs to
+    pub owner_offset: u64,
+    pub source: SlackSource,
+    pub data: Vec<u8>
+}
+impl ser::Serialize for Slack {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        let mut state = serializer.serialize_struct("Slack", 4)?;
+        state.serialize_field("cell_offset", &self.cell_offset)?;
+        state.serialize_field("owner_offset", &self.owner_offset)?;
+        state.serialize_field("source", &self.source)?;
+        state.serialize_field("data", &utils::to_hex_string(&self.data))?;
+        state.end()
+    }
+}
+
+/// Slack of the allocated key and value cells in a hive, along with the values
+/// whose data cells still need to be read for their slack.
+#[derive(Debug)]
+pub struct SlackCells {
+    slack: Vec<Slack>,
+    values: Vec<(u64,ValueKey)>
+}
+impl SlackCells {
+    pub fn from_cells<'a, Rs: Read+Seek>(cells: CellIterator<'a, Rs>) -> SlackCells {
+        let mut slack = Vec::new();
+        let mut values = Vec::new();
+
+        for result in cells {
+            let (cell, cell_data) = match result {
+                Ok(entry) => entry,
+                Err(error) => {
+                    warn!("Skipping cells while scanning for slack: {}",error);
+                    continue;
+                }
+            };
+
+            if !cell.is_allocated() {
+                continue;
+            }
+
+            match cell_data {
+                CellData::NodeKey(nk) => {
+                    if !nk.get_padding().is_empty() {
+                        slack.push(
+                            Slack {
+                                cell_offset: cell.get_offset(),
+                                owner_offset: cell.get_offset(),
+                                source: SlackSource::NodeKey,
+                                data: nk.get_padding().to_vec()
+                            }
+                        );
+                    }
+                },
+                CellData::ValueKey(vk) => {
+                    if !vk.get_padding().is_empty() {
+                        slack.push(
+                            Slack {
+                                cell_offset: cell.get_offset(),
+                                owner_offset: cell.get_offset(),
+                                source: SlackSource::ValueKey,
+                                data: vk.get_padding().to_vec()
+                            }
+                        );
+                    }
+                    if !vk.data_is_resident() {
+                        values.push((cell.get_offset(), vk));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        SlackCells {
+            slack: slack,
+            values: values
+        }
+    }
+
+    /// Set the format version of the hive on the values, so data of hives before 1.4 is
+    /// never read as a db record.
+    pub fn set_version(&mut self, version: HiveVersion) {
+        for &mut (_, ref mut vk) in self.values.iter_mut() {
+            vk.set_version(version);
+        }
+    }
+
+    /// Read the data cell of every non resident value and add its slack.
+    pub fn into_slack<Rs: Read+Seek>(self, reader: &mut Rs) -> Vec<Slack> {
+        let mut slack = self.slack;
+
+        for (owner_offset, mut vk) in self.values {
+            match vk.read_value(reader) {
+                Ok(_) => {
+                    if !vk.get_data_slack().is_empty() {
+                        slack.push(
+                            Slack {
+                                cell_offset: vk.get_data_slack_offset(),
+                                owner_offset: owner_offset,
+                                source: SlackSource::ValueData,
+                                data: vk.get_data_slack().to_vec()
+                            }
+                        );
+                    }
+                },
+                Err(error) => {
+                    debug!("Unable to read data of value at {}: {}",owner_offset,error);
+                }
+            }
+        }
+
+        slack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder,LittleEndian};
+    use std::io::Cursor;
+
+    #[test]
+    fn slack_raw_data_before_big_data() {
+        // A raw data cell that starts with "db", after an empty base block
+        let mut buffer = vec![0; 4096 + 16384];
+        LittleEndian::write_i32(&mut buffer[4096..4100], -16384);
+        buffer[4100..4102].copy_from_slice(b"db");
+
+        let mut vk_buffer = vec![0; 20];
+        vk_buffer[0..2].copy_from_slice(b"vk");
+        LittleEndian::write_u32(&mut vk_buffer[4..8], 16360);
+        LittleEndian::write_u32(&mut vk_buffer[12..16], 3);
+        let vk = ValueKey::new(&vk_buffer, 4).unwrap();
+
+        let mut slack_cells = SlackCells {
+            slack: Vec::new(),
+            values: vec![(8192, vk)]
+        };
+        slack_cells.set_version(HiveVersion::new(1, 3));
+        let slack = slack_cells.into_slack(&mut Cursor::new(buffer));
+        assert_eq!(slack.len(), 1);
+        assert_eq!(slack[0].cell_offset, 4096);
+        assert_eq!(slack[0].data.len(), 20);
+    }
+}
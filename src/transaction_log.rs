@@ -0,0 +1,718 @@
+use byteorder::{ByteOrder,LittleEndian};
+use baseblock::{BaseBlock,compute_checksum};
+use hive::{Hive,HBIN_START_OFFSET};
+use utils;
+use errors::RegError;
+use std::cmp;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::{Seek,SeekFrom};
+
+pub const HVLE_SIGNATURE: u32 = 1162638920;
+pub const DIRT_SIGNATURE: u32 = 1414678852;
+pub const MARVIN32_SEED: u64 = 0x82EF4D887A4E55C5;
+
+// A transaction log file only keeps the first sector of the base block
+const LOG_BASE_BLOCK_SIZE: usize = 512;
+const LOG_SECTOR_SIZE: usize = 512;
+const LOG_ENTRY_HEADER_SIZE: usize = 40;
+
+fn marvin32_block(lo: &mut u32, hi: &mut u32) {
+    *hi ^= *lo;
+    *lo = lo.rotate_left(20).wrapping_add(*hi);
+    *hi = hi.rotate_left(9) ^ *lo;
+    *lo = lo.rotate_left(27).wrapping_add(*hi);
+    *hi = hi.rotate_left(19);
+}
+
+/// The Marvin32 hash used to check log entries.
+pub fn marvin32(seed: u64, buffer: &[u8]) -> u64 {
+    let mut lo = seed as u32;
+    let mut hi = (seed >> 32) as u32;
+
+    let mut chunks = buffer.chunks(4);
+    let remainder = match buffer.len() % 4 {
+        0 => &[][..],
+        _ => chunks.next_back().unwrap()
+    };
+    for chunk in chunks {
+        lo = lo.wrapping_add(LittleEndian::read_u32(chunk));
+        marvin32_block(&mut lo, &mut hi);
+    }
+
+    let last: u32 = match remainder.len() {
+        0 => 0x80,
+        1 => 0x8000 | remainder[0] as u32,
+        2 => 0x800000 | LittleEndian::read_u16(remainder) as u32,
+        _ => 0x80000000 | (remainder[2] as u32) << 16 | LittleEndian::read_u16(remainder) as u32
+    };
+    lo = lo.wrapping_add(last);
+    marvin32_block(&mut lo, &mut hi);
+    marvin32_block(&mut lo, &mut hi);
+
+    (hi as u64) << 32 | lo as u64
+}
+
+#[derive(Serialize, Debug)]
+pub struct DirtyPage {
+    offset: u32, // relative to the start of the hive bins data
+    size: u32,
+    #[serde(skip_serializing)]
+    data: Vec<u8>
+}
+impl DirtyPage {
+    pub fn get_offset(&self)->u32{
+        self.offset
+    }
+
+    pub fn get_size(&self)->u32{
+        self.size
+    }
+
+    pub fn get_data(&self)->&[u8]{
+        &self.data
+    }
+}
+
+// HvLE
+#[derive(Serialize, Debug)]
+pub struct LogEntry {
+    #[serde(skip_serializing)]
+    _offset: u64,
+    signature: u32,
+    size: u32,
+    flags: u32,
+    sequence_number: u32,
+    hive_bins_data_size: u32,
+    dirty_pages_count: u32,
+    hash1: u64, // of the dirty page references and dirty pages
+    hash2: u64, // of the first 32 bytes
+    #[serde(skip_serializing)]
+    _calculated_hash1: u64,
+    #[serde(skip_serializing)]
+    _calculated_hash2: u64,
+    dirty_pages: Vec<DirtyPage>
+}
+impl LogEntry {
+    /// Parse a log entry from a buffer that starts with it. The buffer may extend past the entry.
+    pub fn new(buffer: &[u8], offset: u64) -> Result<LogEntry,RegError> {
+        utils::check_len(buffer, LOG_ENTRY_HEADER_SIZE, "Log entry", offset)?;
+
+        let signature = LittleEndian::read_u32(&buffer[0..4]);
+        let size = LittleEndian::read_u32(&buffer[4..8]);
+        let flags = LittleEndian::read_u32(&buffer[8..12]);
+        let sequence_number = LittleEndian::read_u32(&buffer[12..16]);
+        let hive_bins_data_size = LittleEndian::read_u32(&buffer[16..20]);
+        let dirty_pages_count = LittleEndian::read_u32(&buffer[20..24]);
+        let hash1 = LittleEndian::read_u64(&buffer[24..32]);
+        let hash2 = LittleEndian::read_u64(&buffer[32..40]);
+
+        if signature != HVLE_SIGNATURE {
+            return Err(
+                RegError::validation_error(
+                    format!("Log entry at offset {} has an invalid signature: 0x{:08X}", offset, signature)
+                )
+            );
+        }
+
+        let entry_size = size as usize;
+        let references_end = LOG_ENTRY_HEADER_SIZE as u64 + dirty_pages_count as u64 * 8;
+        if entry_size % LOG_SECTOR_SIZE != 0 || entry_size > buffer.len() || references_end > size as u64 {
+            return Err(
+                RegError::validation_error(
+                    format!("Log entry at offset {} has an invalid size {} for {} dirty pages.",
+                        offset, size, dirty_pages_count
+                    )
+                )
+            );
+        }
+
+        let _calculated_hash1 = marvin32(MARVIN32_SEED, &buffer[LOG_ENTRY_HEADER_SIZE..entry_size]);
+        let _calculated_hash2 = marvin32(MARVIN32_SEED, &buffer[0..32]);
+
+        // The dirty pages follow the references in the same order
+        let mut dirty_pages = Vec::new();
+        let mut page_offset = references_end as usize;
+        for i in 0..dirty_pages_count as usize {
+            let o = LOG_ENTRY_HEADER_SIZE + i * 8;
+            let offset_page = LittleEndian::read_u32(&buffer[o..o + 4]);
+            let size_page = LittleEndian::read_u32(&buffer[o + 4..o + 8]);
+
+            if page_offset + size_page as usize > entry_size {
+                return Err(
+                    RegError::validation_error(
+                        format!("Dirty page {} of log entry at offset {} extends past the entry.", i, offset)
+                    )
+                );
+            }
+
+            dirty_pages.push(
+                DirtyPage {
+                    offset: offset_page,
+                    size: size_page,
+                    data: buffer[page_offset..page_offset + size_page as usize].to_vec()
+                }
+            );
+            page_offset += size_page as usize;
+        }
+
+        Ok(
+            LogEntry {
+                _offset: offset,
+                signature: signature,
+                size: size,
+                flags: flags,
+                sequence_number: sequence_number,
+                hive_bins_data_size: hive_bins_data_size,
+                dirty_pages_count: dirty_pages_count,
+                hash1: hash1,
+                hash2: hash2,
+                _calculated_hash1: _calculated_hash1,
+                _calculated_hash2: _calculated_hash2,
+                dirty_pages: dirty_pages
+            }
+        )
+    }
+
+    pub fn verified(&self)->bool {
+        self.hash1 == self._calculated_hash1 && self.hash2 == self._calculated_hash2
+    }
+
+    pub fn get_size(&self)->u32{
+        self.size
+    }
+
+    pub fn get_sequence_number(&self)->u32{
+        self.sequence_number
+    }
+
+    pub fn get_hive_bins_data_size(&self)->u32{
+        self.hive_bins_data_size
+    }
+
+    pub fn get_dirty_pages(&self)->&Vec<DirtyPage>{
+        &self.dirty_pages
+    }
+}
+
+// DIRT
+#[derive(Serialize, Debug)]
+pub struct DirtyVector {
+    #[serde(skip_serializing)]
+    _offset: u64,
+    signature: u32,
+    dirty_pages: Vec<DirtyPage>
+}
+impl DirtyVector {
+    /// Parse a dirty vector from a buffer that starts with it and holds the dirty sectors after it.
+    ///
+    /// Each bit of the bitmap marks a 512 byte sector of the hive bins data. Runs of
+    /// dirty sectors are combined into a single dirty page.
+    pub fn new(buffer: &[u8], offset: u64, hive_bins_data_size: u32) -> Result<DirtyVector,RegError> {
+        let bitmap_size = hive_bins_data_size as usize / (LOG_SECTOR_SIZE * 8);
+        utils::check_len(buffer, 4 + bitmap_size, "Dirty vector", offset)?;
+
+        let signature = LittleEndian::read_u32(&buffer[0..4]);
+        if signature != DIRT_SIGNATURE {
+            return Err(
+                RegError::validation_error(
+                    format!("Dirty vector at offset {} has an invalid signature: 0x{:08X}", offset, signature)
+                )
+            );
+        }
+
+        let bitmap = &buffer[4..4 + bitmap_size];
+
+        // The dirty sectors start at the next sector boundary
+        let mut data_offset = (4 + bitmap_size + LOG_SECTOR_SIZE - 1) / LOG_SECTOR_SIZE * LOG_SECTOR_SIZE;
+
+        let mut dirty_pages: Vec<DirtyPage> = Vec::new();
+        for sector in 0..bitmap_size * 8 {
+            if bitmap[sector / 8] & (1 << (sector % 8)) == 0 {
+                continue;
+            }
+
+            if data_offset + LOG_SECTOR_SIZE > buffer.len() {
+                return Err(
+                    RegError::validation_error(
+                        format!("Dirty vector at offset {} is missing dirty sector {}.", offset, sector)
+                    )
+                );
+            }
+            let data = &buffer[data_offset..data_offset + LOG_SECTOR_SIZE];
+            data_offset += LOG_SECTOR_SIZE;
+
+            let sector_offset = (sector * LOG_SECTOR_SIZE) as u32;
+            let mut extend_last = false;
+            if let Some(last) = dirty_pages.last() {
+                extend_last = last.offset + last.size == sector_offset;
+            }
+
+            if extend_last {
+                let last = dirty_pages.last_mut().unwrap();
+                last.size += LOG_SECTOR_SIZE as u32;
+                last.data.extend_from_slice(data);
+            } else {
+                dirty_pages.push(
+                    DirtyPage {
+                        offset: sector_offset,
+                        size: LOG_SECTOR_SIZE as u32,
+                        data: data.to_vec()
+                    }
+                );
+            }
+        }
+
+        Ok(
+            DirtyVector {
+                _offset: offset,
+                signature: signature,
+                dirty_pages: dirty_pages
+            }
+        )
+    }
+
+    pub fn get_dirty_pages(&self)->&Vec<DirtyPage>{
+        &self.dirty_pages
+    }
+}
+
+/// A .LOG, .LOG1 or .LOG2 transaction log file in either the new (HvLE) or old (DIRT) format.
+#[derive(Serialize, Debug)]
+pub struct TransactionLog {
+    baseblock: BaseBlock,
+    log_entries: Vec<LogEntry>,
+    dirty_vector: Option<DirtyVector>
+}
+impl TransactionLog {
+    pub fn from_source<R: Read+Seek>(mut source: R) -> Result<TransactionLog,RegError> {
+        source.seek(
+            SeekFrom::Start(0)
+        )?;
+        let mut buffer = Vec::new();
+        source.read_to_end(&mut buffer)?;
+
+        if buffer.len() < LOG_BASE_BLOCK_SIZE {
+            return Err(
+                RegError::validation_error(
+                    format!("Transaction log needs at least {} bytes, got {}.", LOG_BASE_BLOCK_SIZE, buffer.len())
+                )
+            );
+        }
+
+        let mut buffer_baseblock = [0; 4096];
+        buffer_baseblock[0..LOG_BASE_BLOCK_SIZE].copy_from_slice(&buffer[0..LOG_BASE_BLOCK_SIZE]);
+        let baseblock = BaseBlock::new(&buffer_baseblock, 0)?;
+
+        let mut log_entries = Vec::new();
+        let mut dirty_vector = None;
+
+        if buffer.len() >= LOG_BASE_BLOCK_SIZE + 4 {
+            match LittleEndian::read_u32(&buffer[LOG_BASE_BLOCK_SIZE..LOG_BASE_BLOCK_SIZE + 4]) {
+                HVLE_SIGNATURE => {
+                    // Log entries are read until one is missing or fails its hash check
+                    let mut offset = LOG_BASE_BLOCK_SIZE;
+                    while offset + LOG_ENTRY_HEADER_SIZE <= buffer.len() {
+                        let log_entry = match LogEntry::new(&buffer[offset..], offset as u64) {
+                            Ok(log_entry) => log_entry,
+                            Err(error) => {
+                                debug!("End of log entries: {}",error);
+                                break;
+                            }
+                        };
+
+                        if !log_entry.verified() {
+                            warn!("Log entry at offset {} failed its hash check.",offset);
+                            break;
+                        }
+
+                        offset += log_entry.get_size() as usize;
+                        log_entries.push(log_entry);
+                    }
+                },
+                DIRT_SIGNATURE => {
+                    dirty_vector = Some(
+                        DirtyVector::new(
+                            &buffer[LOG_BASE_BLOCK_SIZE..],
+                            LOG_BASE_BLOCK_SIZE as u64,
+                            baseblock.hive_bins_data_size()
+                        )?
+                    );
+                },
+                _ => {}
+            }
+        }
+
+        Ok(
+            TransactionLog {
+                baseblock: baseblock,
+                log_entries: log_entries,
+                dirty_vector: dirty_vector
+            }
+        )
+    }
+
+    pub fn get_baseblock(&self)->&BaseBlock{
+        &self.baseblock
+    }
+
+    pub fn get_log_entries(&self)->&Vec<LogEntry>{
+        &self.log_entries
+    }
+
+    pub fn get_dirty_vector(&self)->&Option<DirtyVector>{
+        &self.dirty_vector
+    }
+}
+
+/// Write dirty pages over the hive. The hive bins data size is taken from the log and
+/// can only grow the hive as far as the primary file or the dirty pages reach.
+fn apply_dirty_pages(buffer: &mut Vec<u8>, primary_size: usize, hive_bins_data_size: u32, dirty_pages: &[DirtyPage])->Result<(),RegError> {
+    let highest_end = dirty_pages.iter()
+        .map(|dirty_page| HBIN_START_OFFSET as usize + dirty_page.offset as usize + dirty_page.data.len())
+        .max()
+        .unwrap_or(0);
+    let size = HBIN_START_OFFSET as usize + hive_bins_data_size as usize;
+    if size > cmp::max(primary_size, highest_end) {
+        return Err(
+            RegError::validation_error(
+                format!("Hive bins data size {} is past the primary file ({} bytes) and the last dirty page (ending at {}).",
+                    hive_bins_data_size, primary_size, highest_end
+                )
+            )
+        );
+    }
+    buffer.resize(size, 0);
+
+    for dirty_page in dirty_pages {
+        let start = HBIN_START_OFFSET as usize + dirty_page.offset as usize;
+        let end = start + dirty_page.data.len();
+        if end > buffer.len() {
+            return Err(
+                RegError::validation_error(
+                    format!("Dirty page at offset {} with size {} is past the hive bins data size {}.",
+                        dirty_page.offset, dirty_page.size, hive_bins_data_size
+                    )
+                )
+            );
+        }
+        buffer[start..end].copy_from_slice(&dirty_page.data);
+    }
+
+    Ok(())
+}
+
+fn update_baseblock(buffer: &mut Vec<u8>, sequence_number: u32, hive_bins_data_size: u32) {
+    LittleEndian::write_u32(&mut buffer[4..8], sequence_number);
+    LittleEndian::write_u32(&mut buffer[8..12], sequence_number);
+    LittleEndian::write_u32(&mut buffer[40..44], hive_bins_data_size);
+
+    let mut buffer_baseblock = [0; 4096];
+    buffer_baseblock.copy_from_slice(&buffer[0..4096]);
+    let checksum = compute_checksum(&buffer_baseblock);
+    LittleEndian::write_u32(&mut buffer[508..512], checksum);
+}
+
+/// Replay transaction logs over the bytes of a primary hive file.
+///
+/// New format log entries from all logs are applied in sequence number order. The first
+/// must have the secondary sequence number of the primary base block, so a stale log is
+/// not replayed, and the replay stops at the first gap. When no log entry applies, the newest old format dirty vector is used instead.
+/// The base block is updated to the last applied sequence number. Returns the number of
+/// log entries or dirty vectors applied, which is 0 when the hive is not dirty.
+pub fn apply_transaction_logs(buffer: &mut Vec<u8>, logs: &[TransactionLog])->Result<usize,RegError> {
+    if buffer.len() < 4096 {
+        return Err(
+            RegError::validation_error(
+                format!("Hive needs at least 4096 bytes, got {}.", buffer.len())
+            )
+        );
+    }
+
+    let mut buffer_baseblock = [0; 4096];
+    buffer_baseblock.copy_from_slice(&buffer[0..4096]);
+    let baseblock = BaseBlock::new(&buffer_baseblock, 0)?;
+    if !baseblock.is_dirty() {
+        return Ok(0);
+    }
+
+    let mut log_entries: Vec<&LogEntry> = logs.iter()
+        .flat_map(|log| log.get_log_entries().iter())
+        .filter(|entry| entry.get_sequence_number() >= baseblock.secondary_seq_num())
+        .collect();
+    log_entries.sort_by_key(|entry| entry.get_sequence_number());
+
+    let primary_size = buffer.len();
+    let mut applied = 0;
+    let mut sequence_number = baseblock.secondary_seq_num();
+    let mut hive_bins_data_size = baseblock.hive_bins_data_size();
+    for log_entry in log_entries {
+        if applied == 0 {
+            if log_entry.get_sequence_number() != sequence_number {
+                warn!("Log entries start at sequence number {}, expected {}.",
+                    log_entry.get_sequence_number(), sequence_number
+                );
+                break;
+            }
+        } else {
+            if log_entry.get_sequence_number() == sequence_number {
+                // The same entry can be in both logs
+                continue;
+            }
+            if log_entry.get_sequence_number() != sequence_number + 1 {
+                warn!("Log entries stop at sequence number {}, next is {}.",
+                    sequence_number, log_entry.get_sequence_number()
+                );
+                break;
+            }
+        }
+
+        apply_dirty_pages(buffer, primary_size, log_entry.get_hive_bins_data_size(), log_entry.get_dirty_pages())?;
+        sequence_number = log_entry.get_sequence_number();
+        hive_bins_data_size = log_entry.get_hive_bins_data_size();
+        applied += 1;
+    }
+
+    if applied == 0 {
+        let mut newest: Option<&TransactionLog> = None;
+        for log in logs {
+            let log_baseblock = log.get_baseblock();
+            if log.get_dirty_vector().is_none() || !log_baseblock.verified() || log_baseblock.is_dirty() {
+                continue;
+            }
+            if log_baseblock.primary_seq_num() < baseblock.secondary_seq_num() {
+                continue;
+            }
+            newest = match newest {
+                Some(other) if other.get_baseblock().primary_seq_num() >= log_baseblock.primary_seq_num() => Some(other),
+                _ => Some(log)
+            };
+        }
+
+        if let Some(log) = newest {
+            if let Some(ref dirty_vector) = *log.get_dirty_vector() {
+                hive_bins_data_size = log.get_baseblock().hive_bins_data_size();
+                apply_dirty_pages(buffer, primary_size, hive_bins_data_size, dirty_vector.get_dirty_pages())?;
+                sequence_number = log.get_baseblock().primary_seq_num();
+                applied += 1;
+            }
+        }
+    }
+
+    if applied > 0 {
+        update_baseblock(buffer, sequence_number, hive_bins_data_size);
+    }
+
+    Ok(applied)
+}
+
+/// Replay transaction logs over a primary hive and open the result as an in memory hive.
+pub fn recover_hive<R: Read+Seek, L: Read+Seek>(mut primary: R, logs: Vec<L>)->Result<Hive<Cursor<Vec<u8>>>,RegError> {
+    primary.seek(
+        SeekFrom::Start(0)
+    )?;
+    let mut buffer = Vec::new();
+    primary.read_to_end(&mut buffer)?;
+
+    let mut transaction_logs = Vec::new();
+    for log in logs {
+        transaction_logs.push(
+            TransactionLog::from_source(log)?
+        );
+    }
+
+    let applied = apply_transaction_logs(&mut buffer, &transaction_logs)?;
+    debug!("applied {} transaction log entries",applied);
+
+    Hive::from_source(
+        Cursor::new(buffer)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::io::Read;
+    use std::fs::File;
+
+    // Offset of the "User Agent" value data relative to the first hive bin
+    const USER_AGENT_DATA: usize = 12416 + 4;
+
+    fn read_primary() -> Vec<u8> {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = Vec::new();
+
+        match file.read_to_end(&mut buffer){
+            Err(error)=>panic!("{:?}",error),
+            _ => {}
+        }
+        buffer
+    }
+
+    fn log_entry(sequence_number: u32, hive_bins_data_size: u32, pages: &[(u32,&[u8])]) -> Vec<u8> {
+        let mut entry = vec![0; 40];
+        LittleEndian::write_u32(&mut entry[0..4], HVLE_SIGNATURE);
+        LittleEndian::write_u32(&mut entry[12..16], sequence_number);
+        LittleEndian::write_u32(&mut entry[16..20], hive_bins_data_size);
+        LittleEndian::write_u32(&mut entry[20..24], pages.len() as u32);
+        for &(offset, data) in pages {
+            let mut reference = [0; 8];
+            LittleEndian::write_u32(&mut reference[0..4], offset);
+            LittleEndian::write_u32(&mut reference[4..8], data.len() as u32);
+            entry.extend_from_slice(&reference);
+        }
+        for &(_, data) in pages {
+            entry.extend_from_slice(data);
+        }
+
+        let size = (entry.len() + 511) / 512 * 512;
+        entry.resize(size, 0);
+        LittleEndian::write_u32(&mut entry[4..8], size as u32);
+
+        let hash1 = marvin32(MARVIN32_SEED, &entry[40..]);
+        LittleEndian::write_u64(&mut entry[24..32], hash1);
+        let hash2 = marvin32(MARVIN32_SEED, &entry[0..32]);
+        LittleEndian::write_u64(&mut entry[32..40], hash2);
+        entry
+    }
+
+    fn log_file(primary: &[u8], entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut log = primary[0..512].to_vec();
+        for entry in entries {
+            log.extend_from_slice(entry);
+        }
+        log
+    }
+
+    #[test]
+    fn marvin32_hash() {
+        let seed = 0x004FB61A001BDBCC;
+        assert_eq!(marvin32(seed, &[]), 0x30ED35C100CD3C7D);
+        assert_eq!(marvin32(seed, &[0xAF]), 0x48E73FC77D75DDC1);
+        assert_eq!(marvin32(seed, &[0xE7,0x0F]), 0xB5F6E1FC485DBFF8);
+        assert_eq!(marvin32(seed, &[0x37,0xF4,0x95]), 0xF0B07C789B8CF7E8);
+        assert_eq!(marvin32(seed, &[0x86,0x42,0xDC,0x59]), 0x7008F2E87E9CF556);
+    }
+
+    #[test]
+    fn log_entries() {
+        let mut primary = read_primary();
+
+        // Change "Mozilla" to "Nozilla" in the page holding the User Agent data
+        let mut page = primary[4096 + 12288..4096 + 16384].to_vec();
+        page[USER_AGENT_DATA - 12288] = 0x4E;
+        let entry1 = log_entry(2809, 3563520, &[(12288, &page)]);
+        page[USER_AGENT_DATA - 12288] = 0x4F;
+        let entry2 = log_entry(2810, 3563520, &[(12288, &page)]);
+        // A gap in sequence numbers ends the replay
+        page[USER_AGENT_DATA - 12288] = 0x50;
+        let entry4 = log_entry(2812, 3563520, &[(12288, &page)]);
+
+        // Entries are replayed in sequence number order across logs
+        let log1 = TransactionLog::from_source(
+            Cursor::new(log_file(&primary, &[entry2, entry4]))
+        ).unwrap();
+        let log2 = TransactionLog::from_source(
+            Cursor::new(log_file(&primary, &[entry1]))
+        ).unwrap();
+        assert_eq!(log1.get_log_entries().len(), 2);
+        assert!(log1.get_log_entries()[0].verified());
+
+        let applied = apply_transaction_logs(&mut primary, &[log1, log2]).unwrap();
+        assert_eq!(applied, 2);
+        assert_eq!(primary[4096 + USER_AGENT_DATA], 0x4F);
+
+        let mut buffer_baseblock = [0; 4096];
+        buffer_baseblock.copy_from_slice(&primary[0..4096]);
+        let baseblock = BaseBlock::new(&buffer_baseblock, 0).unwrap();
+        assert_eq!(baseblock.primary_seq_num(), 2810);
+        assert_eq!(baseblock.secondary_seq_num(), 2810);
+        assert_eq!(baseblock.verified(), true);
+        assert_eq!(baseblock.is_dirty(), false);
+    }
+
+    #[test]
+    fn log_entries_start_past_hive() {
+        let mut primary = read_primary();
+        let original = primary.clone();
+
+        // The hive expects sequence number 2809 next
+        let mut page = primary[4096 + 12288..4096 + 16384].to_vec();
+        page[USER_AGENT_DATA - 12288] = 0x4E;
+        let entry = log_entry(2810, 3563520, &[(12288, &page)]);
+
+        let log = TransactionLog::from_source(
+            Cursor::new(log_file(&primary, &[entry]))
+        ).unwrap();
+        assert_eq!(apply_transaction_logs(&mut primary, &[log]).unwrap(), 0);
+        assert!(primary == original);
+    }
+
+    #[test]
+    fn log_entry_bad_hash() {
+        let primary = read_primary();
+        let page = primary[4096 + 12288..4096 + 16384].to_vec();
+        let mut entry = log_entry(2809, 3563520, &[(12288, &page)]);
+        entry[100] ^= 0xFF;
+
+        let log = TransactionLog::from_source(
+            Cursor::new(log_file(&primary, &[entry]))
+        ).unwrap();
+        assert_eq!(log.get_log_entries().len(), 0);
+    }
+
+    #[test]
+    fn log_entry_data_size_past_hive() {
+        let mut primary = read_primary();
+        let page = primary[4096 + 12288..4096 + 16384].to_vec();
+        let entry = log_entry(2809, 0xFFFFF000, &[(12288, &page)]);
+
+        let log = TransactionLog::from_source(
+            Cursor::new(log_file(&primary, &[entry]))
+        ).unwrap();
+        assert!(apply_transaction_logs(&mut primary, &[log]).is_err());
+        assert_eq!(primary.len(), 3670016);
+    }
+
+    #[test]
+    fn dirty_vector() {
+        let mut primary = read_primary();
+
+        // Old format: dirty vector with a bit per sector, then the dirty sectors
+        let mut log = primary[0..512].to_vec();
+        LittleEndian::write_u32(&mut log[4..8], 2810);
+        LittleEndian::write_u32(&mut log[8..12], 2810);
+        let mut buffer_baseblock = [0; 4096];
+        buffer_baseblock[0..512].copy_from_slice(&log[0..512]);
+        let checksum = compute_checksum(&buffer_baseblock);
+        LittleEndian::write_u32(&mut log[508..512], checksum);
+
+        let bitmap_size = 3563520 / 4096;
+        let mut vector = vec![0; 4 + bitmap_size];
+        LittleEndian::write_u32(&mut vector[0..4], DIRT_SIGNATURE);
+        // Sectors 24 and 25 hold the User Agent data
+        vector[4 + 3] = 0x03;
+        log.extend_from_slice(&vector);
+        let aligned = (log.len() + 511) / 512 * 512;
+        log.resize(aligned, 0);
+
+        let mut sectors = primary[4096 + 12288..4096 + 12288 + 1024].to_vec();
+        sectors[USER_AGENT_DATA - 12288] = 0x4E;
+        log.extend_from_slice(&sectors);
+
+        let log = TransactionLog::from_source(Cursor::new(log)).unwrap();
+        match *log.get_dirty_vector() {
+            Some(ref dirty_vector) => {
+                assert_eq!(dirty_vector.get_dirty_pages().len(), 1);
+                assert_eq!(dirty_vector.get_dirty_pages()[0].get_offset(), 12288);
+                assert_eq!(dirty_vector.get_dirty_pages()[0].get_size(), 1024);
+            },
+            None => panic!("no dirty vector")
+        }
+
+        let applied = apply_transaction_logs(&mut primary, &[log]).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(primary[4096 + USER_AGENT_DATA], 0x4E);
+    }
+}
@@ -2,6 +2,19 @@ use encoding::{Encoding, DecoderTrap};
 use encoding::all::UTF_16LE;
 use encoding::all::ASCII;
 use errors::RegError;
+use std::cmp::Ordering;
+
+/// Check that a buffer holds the fixed size part of a structure before it is read.
+pub fn check_len(buffer: &[u8], needed: usize, name: &str, offset: u64) -> Result<(),RegError> {
+    if buffer.len() < needed {
+        return Err(
+            RegError::validation_error(
+                format!("{} at offset {} needs {} bytes, got {}.", name, offset, needed, buffer.len())
+            )
+        );
+    }
+    Ok(())
+}
 
 pub fn read_ascii(buffer: &[u8]) -> Result<String,RegError> {
     let mut end_index = buffer.len();
@@ -54,11 +67,87 @@ pub fn read_utf16(buffer: &[u8]) -> Result<String,RegError> {
     Ok(utf16_string)
 }
 
+/// Read a list of null terminated UTF-16 strings. The list ends at an empty string
+/// or at the end of the buffer, whichever comes first.
+pub fn read_multi_string(buffer: &[u8]) -> Result<Vec<String>,RegError> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + 1 < buffer.len() {
+        if buffer[i] == 0x00 && buffer[i+1] == 0x00 {
+            if i == start {
+                return Ok(strings);
+            }
+            strings.push(read_utf16(&buffer[start..i])?);
+            start = i + 2;
+        }
+        i += 2;
+    }
+
+    // The last string is not terminated
+    if i > start {
+        strings.push(read_utf16(&buffer[start..i])?);
+    }
+
+    Ok(strings)
+}
+
+/// Find the byte offset of the first null UTF-16 code unit.
+pub fn find_utf16_null(buffer: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i + 1 < buffer.len() {
+        if buffer[i] == 0x00 && buffer[i+1] == 0x00 {
+            return Some(i);
+        }
+        i += 2;
+    }
+    None
+}
+
+/// Decode UTF-16 LE without dropping characters. Unpaired surrogates are replaced with
+/// U+FFFD and reported by returning false. An odd trailing byte is not decoded.
+pub fn read_utf16_lossy(buffer: &[u8]) -> (String, bool) {
+    let units: Vec<u16> = buffer.chunks(2)
+        .filter(|chunk| chunk.len() == 2)
+        .map(|chunk| chunk[0] as u16 | (chunk[1] as u16) << 8)
+        .collect();
+
+    match String::from_utf16(&units) {
+        Ok(string) => (string, true),
+        Err(_) => (String::from_utf16_lossy(&units), false)
+    }
+}
+
+/// Check whether data that should be UTF-16 is actually printable ASCII. The text must
+/// be at least two characters long and either be null terminated or have an odd length,
+/// and must not also read as null terminated UTF-16 without control characters: the
+/// bytes 41 61 00 00 are U+6141, not "Aa".
+pub fn is_ascii_text(buffer: &[u8]) -> bool {
+    if let Some(utf16_end) = find_utf16_null(buffer) {
+        let (value, valid) = read_utf16_lossy(&buffer[0..utf16_end]);
+        if valid && !value.chars().any(|c| c.is_control()) {
+            return false;
+        }
+    }
+
+    let end = match buffer.iter().position(|&b| b == 0) {
+        Some(end) => end,
+        None => {
+            if buffer.len() % 2 == 0 {
+                return false;
+            }
+            buffer.len()
+        }
+    };
+
+    end >= 2 && buffer[0..end].iter().all(|&b| (b >= 0x20 && b < 0x7F) || b == b'\t' || b == b'\r' || b == b'\n')
+}
+
 pub fn read_string_u16_till_null(buffer: &[u8])->Result<String,RegError> {
     let mut end_index: usize = 0;
     let buf_len = buffer.len();
     let mut i = 0;
-    while i < buf_len {
+    while i + 1 < buf_len {
         if buffer[i] == 0x00 && buffer[i+1] == 0x00 {
             break
         } else {
@@ -88,3 +177,121 @@ pub fn to_hex_string(bytes: &[u8]) -> String {
         .collect();
     strs.join("")
 }
+
+const BASE64_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard base64 with padding.
+pub fn to_base64_string(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = if chunk.len() > 1 { chunk[1] as usize } else { 0 };
+        let b2 = if chunk.len() > 2 { chunk[2] as usize } else { 0 };
+
+        encoded.push(BASE64_ALPHABET[b0 >> 2] as char);
+        encoded.push(BASE64_ALPHABET[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+        if chunk.len() > 1 {
+            encoded.push(BASE64_ALPHABET[((b1 & 0x0F) << 2) | (b2 >> 6)] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(BASE64_ALPHABET[b2 & 0x3F] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+/// Upper case a name one character at a time the way the registry compares names.
+/// Characters without a single character upper case form are left as they are.
+pub fn upcase(name: &str) -> String {
+    name.chars().map(|c| {
+        let mut upper = c.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(u), None) => u,
+            _ => c
+        }
+    }).collect()
+}
+
+pub fn names_equal(name1: &str, name2: &str) -> bool {
+    upcase(name1) == upcase(name2)
+}
+
+/// The name hash stored in lh lists: every upper cased UTF-16 code unit of the
+/// name is added to the hash after multiplying it by 37.
+pub fn name_hash(name: &str) -> u32 {
+    upcase(name).encode_utf16().fold(0u32, |hash, c| {
+        hash.wrapping_mul(37).wrapping_add(c as u32)
+    })
+}
+
+/// Compare two names the way subkey lists are sorted, by their upper cased UTF-16 code units.
+pub fn compare_names(name1: &str, name2: &str) -> Ordering {
+    upcase(name1).encode_utf16().cmp(upcase(name2).encode_utf16())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_hashes() {
+        assert_eq!(name_hash(""), 0);
+        assert_eq!(name_hash("a"), 65);
+        assert_eq!(name_hash("ab"), 65 * 37 + 66);
+        assert_eq!(name_hash("Software"), name_hash("SOFTWARE"));
+    }
+
+    #[test]
+    fn string_u16_odd_length() {
+        assert_eq!(read_string_u16_till_null(&[0x41,0x00,0x42]).unwrap(), "A");
+        assert_eq!(read_string_u16_till_null(&[0x41,0x00,0x00,0x00,0x42,0x00]).unwrap(), "A");
+    }
+
+    #[test]
+    fn multi_string() {
+        let buffer = [0x41,0x00,0x00,0x00,0x42,0x00,0x43,0x00,0x00,0x00,0x00,0x00,0x44,0x00];
+        assert_eq!(read_multi_string(&buffer).unwrap(), vec!["A","BC"]);
+        assert_eq!(read_multi_string(&buffer[0..6]).unwrap(), vec!["A","B"]);
+        assert!(read_multi_string(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ascii_text() {
+        assert!(is_ascii_text(b"C:\\Windows\0"));
+        assert!(is_ascii_text(b"abc"));
+        assert!(!is_ascii_text(b"abcd"));
+        assert!(!is_ascii_text(&[0x43,0x00,0x3A,0x00,0x00,0x00]));
+        assert!(!is_ascii_text(&[0x43,0x3A,0x01,0x00]));
+        // U+6141 and a terminator, not "Aa"
+        assert!(!is_ascii_text(&[0x41,0x61,0x00,0x00]));
+    }
+
+    #[test]
+    fn utf16_lossy() {
+        assert_eq!(read_utf16_lossy(&[0x41,0x00,0x42]), ("A".to_string(), true));
+        assert_eq!(read_utf16_lossy(&[0x00,0xD8,0x41,0x00]), ("\u{FFFD}A".to_string(), false));
+        assert_eq!(find_utf16_null(&[0x41,0x00,0x00,0x42,0x00,0x00]), Some(4));
+    }
+
+    #[test]
+    fn base64_string() {
+        assert_eq!(to_base64_string(b""), "");
+        assert_eq!(to_base64_string(b"f"), "Zg==");
+        assert_eq!(to_base64_string(b"fo"), "Zm8=");
+        assert_eq!(to_base64_string(b"foo"), "Zm9v");
+        assert_eq!(to_base64_string(b"foobar"), "Zm9vYmFy");
+        assert_eq!(to_base64_string(&[0xFF,0xFE]), "//4=");
+    }
+
+    #[test]
+    fn name_ordering() {
+        assert_eq!(compare_names("software", "SOFTWARE"), Ordering::Equal);
+        assert_eq!(compare_names("Classes", "software"), Ordering::Less);
+        // '_' sorts after the upper case letters but before the lower case ones
+        assert_eq!(compare_names("a_b", "AZ"), Ordering::Greater);
+    }
+}
@@ -0,0 +1,581 @@
+use hive::HBIN_START_OFFSET;
+use baseblock::{BaseBlock,HiveVersion};
+use hbin::{HiveBinIterator,HBIN_HEADER_SIZE,HBIN_ALIGNMENT};
+use cell::Cell;
+use cell::CellData;
+use nk::NodeKey;
+use vk::ValueKeyList;
+use sk::SecurityKeyList;
+use byteorder::{ByteOrder,LittleEndian};
+use std::collections::HashMap;
+use std::io::{Read,Seek};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    /// The base block checksum does not match its first 508 bytes
+    ChecksumMismatch,
+    /// The primary and secondary sequence numbers differ
+    DirtySequenceNumbers,
+    /// A hive bin has a bad signature, size or offset, or runs past the hive bins data
+    HiveBinError,
+    /// A cell size is too small or not a multiple of 8
+    InvalidCellSize,
+    /// A cell runs past the end of its hive bin
+    CellCrossesHiveBin,
+    /// The subkey lists of a key hold a different number of keys than num_sub_keys
+    SubKeyCountMismatch,
+    /// The value list of a key is missing or too small for num_values
+    ValueCountMismatch,
+    /// The parent offset of a key is not the key whose subkey list holds it
+    ParentMismatch,
+    /// A cell that should only have one owner is referenced more than once
+    CellReferencedTwice,
+    /// A cell referenced by the key tree could not be read
+    UnreadableCell,
+    /// The format version is unknown, or a structure is not valid for it
+    VersionMismatch,
+    /// The security key list does not link back on itself, or a key points at a security
+    /// key that is not on it
+    SecurityKeyListBroken,
+    /// The reference count of a security key is not the number of keys pointing at it
+    ReferenceCountMismatch
+}
+
+/// A structural inconsistency found while verifying a hive.
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// Absolute offset of the structure with the issue
+    pub offset: u64,
+    /// Path of the key the issue was found under (empty outside of the key tree)
+    pub path: String,
+    pub message: String
+}
+
+/// The issues found in a hive by Hive::verify.
+#[derive(Serialize, Debug)]
+pub struct VerifyReport {
+    pub issues: Vec<Issue>
+}
+impl VerifyReport {
+    pub fn new() -> VerifyReport {
+        VerifyReport {
+            issues: Vec::new()
+        }
+    }
+
+    /// No issue was found.
+    pub fn is_clean(&self)->bool {
+        self.issues.is_empty()
+    }
+
+    pub fn count(&self, kind: IssueKind)->usize {
+        self.issues.iter().filter(|issue| issue.kind == kind).count()
+    }
+
+    fn push(&mut self, kind: IssueKind, offset: u64, path: &str, message: String) {
+        debug!("{:?} at offset {}: {}",kind,offset,message);
+        self.issues.push(
+            Issue {
+                kind: kind,
+                offset: offset,
+                path: path.to_string(),
+                message: message
+            }
+        );
+    }
+
+    /// Check the checksum and sequence numbers of the base block.
+    pub fn check_baseblock(&mut self, baseblock: &BaseBlock) {
+        if !baseblock.verified() {
+            self.push(
+                IssueKind::ChecksumMismatch, 0, "",
+                format!("Base block checksum does not match its contents.")
+            );
+        }
+
+        if baseblock.is_dirty() {
+            self.push(
+                IssueKind::DirtySequenceNumbers, 4, "",
+                format!("Primary sequence number {} does not match secondary sequence number {}.",
+                    baseblock.primary_seq_num(), baseblock.secondary_seq_num()
+                )
+            );
+        }
+
+        if !baseblock.get_version().is_supported() {
+            self.push(
+                IssueKind::VersionMismatch, 20, "",
+                format!("Hive format version {} is not a known version.", baseblock.get_version())
+            );
+        }
+    }
+
+    /// Check the hive bin headers and that the cells of each hive bin end on its boundary.
+    pub fn check_hive_bins<'a, Rs: Read+Seek>(&mut self, mut hive_bins: HiveBinIterator<'a, Rs>) {
+        let mut expected_offset = HBIN_START_OFFSET;
+
+        loop {
+            let hive_bin = match hive_bins.next() {
+                Some(Ok(hive_bin)) => hive_bin,
+                Some(Err(error)) => {
+                    self.push(
+                        IssueKind::HiveBinError, expected_offset, "",
+                        format!("Hive bin could not be read: {}", error)
+                    );
+                    continue;
+                },
+                None => break
+            };
+            let offset = hive_bin.get_offset();
+
+            if !hive_bin.has_valid_signature() {
+                self.push(
+                    IssueKind::HiveBinError, offset, "",
+                    format!("Hive bin at offset {} has an invalid signature.", offset)
+                );
+            }
+            if !hive_bin.has_valid_size() {
+                self.push(
+                    IssueKind::HiveBinError, offset, "",
+                    format!("Hive bin at offset {} has an invalid size: {}", offset, hive_bin.get_size())
+                );
+            }
+            if !hive_bin.has_valid_offset() {
+                self.push(
+                    IssueKind::HiveBinError, offset, "",
+                    format!("Hive bin at offset {} has a relative offset of {}.", offset, hive_bin.get_relative_offset())
+                );
+            }
+
+            if !hive_bin.is_valid() {
+                expected_offset = offset + HBIN_ALIGNMENT as u64;
+                continue;
+            }
+            expected_offset = offset + hive_bin.get_size() as u64;
+
+            let buffer = match hive_bins.read_cell_buffer(&hive_bin) {
+                Ok(buffer) => buffer,
+                Err(error) => {
+                    self.push(
+                        IssueKind::HiveBinError, offset, "",
+                        format!("{}", error)
+                    );
+                    continue;
+                }
+            };
+            self.check_cells(&buffer, offset + HBIN_HEADER_SIZE as u64);
+        }
+    }
+
+    /// Walk the cell sizes of a hive bin's cell area.
+    fn check_cells(&mut self, buffer: &[u8], buffer_offset: u64) {
+        let mut position = 0;
+        while position < buffer.len() {
+            let offset = buffer_offset + position as u64;
+            let remaining = buffer.len() - position;
+            if remaining < 4 {
+                self.push(
+                    IssueKind::CellCrossesHiveBin, offset, "",
+                    format!("Cell at offset {} has no room for a size before the end of its hive bin.", offset)
+                );
+                return;
+            }
+
+            let size = LittleEndian::read_i32(&buffer[position..position + 4]);
+            let cell_size = (size as i64).abs() as usize;
+            if cell_size < 8 || cell_size % 8 != 0 {
+                self.push(
+                    IssueKind::InvalidCellSize, offset, "",
+                    format!("Cell at offset {} has an invalid size: {}", offset, size)
+                );
+                return;
+            }
+            if cell_size > remaining {
+                self.push(
+                    IssueKind::CellCrossesHiveBin, offset, "",
+                    format!("Cell at offset {} with size {} runs {} bytes past the end of its hive bin.",
+                        offset, cell_size, cell_size - remaining
+                    )
+                );
+                return;
+            }
+
+            position += cell_size;
+        }
+    }
+
+    /// Walk the key tree from the root key, checking the counts and parent offsets of
+    /// every key and that no cell is owned by two structures. Security keys are shared
+    /// between keys and are not counted as references; instead their list is walked and
+    /// their reference counts checked against the keys pointing at them.
+    pub fn check_keys<Rs: Read+Seek>(&mut self, reader: &mut Rs, root_offset: u32, version: HiveVersion) {
+        let root = match NodeKey::at_offset(reader, root_offset) {
+            Ok(nk) => nk,
+            Err(error) => {
+                self.push(
+                    IssueKind::UnreadableCell, root_offset as u64 + HBIN_START_OFFSET, "",
+                    format!("Root key could not be read: {}", error)
+                );
+                return;
+            }
+        };
+
+        let mut referenced = HashMap::new();
+        let root_path = format!("\\{}",root.key_name());
+        referenced.insert(root_offset, root_path.clone());
+
+        // The security key offset of each key walked, with its path
+        let mut key_security = Vec::new();
+        let security_offset = root.get_security_key_offset();
+
+        let mut stack = vec![(root_offset, root_path, root)];
+        while let Some((offset, path, nk)) = stack.pop() {
+            if let Some(sk_offset) = nk.get_security_id() {
+                key_security.push((sk_offset, path.clone()));
+            }
+            if nk.get_class_name_offset() != 4294967295 {
+                self.reference(&mut referenced, nk.get_class_name_offset(), &path);
+            }
+            self.check_values(reader, &mut referenced, offset, &path, &nk);
+
+            let sub_key_offsets = self.check_sub_key_lists(reader, &mut referenced, offset, &path, &nk, &version);
+            for sub_key_offset in sub_key_offsets.into_iter().rev() {
+                if !self.reference(&mut referenced, sub_key_offset, &path) {
+                    // Do not walk the same key twice, it could be an ancestor
+                    continue;
+                }
+
+                let sub_key = match NodeKey::at_offset(reader, sub_key_offset) {
+                    Ok(sub_key) => sub_key,
+                    Err(error) => {
+                        self.push(
+                            IssueKind::UnreadableCell, sub_key_offset as u64 + HBIN_START_OFFSET, &path,
+                            format!("{}", error)
+                        );
+                        continue;
+                    }
+                };
+
+                let sub_key_path = format!("{}\\{}",path,sub_key.key_name());
+                if sub_key.get_parent_key_offset() != offset {
+                    self.push(
+                        IssueKind::ParentMismatch, sub_key_offset as u64 + HBIN_START_OFFSET, &sub_key_path,
+                        format!("Key has a parent offset of {} but is a subkey of the key at offset {}.",
+                            sub_key.get_parent_key_offset(), offset
+                        )
+                    );
+                }
+
+                stack.push((sub_key_offset, sub_key_path, sub_key));
+            }
+        }
+
+        self.check_security_keys(reader, security_offset, &key_security);
+    }
+
+    /// Walk the security key list from the security key of the root key and check the
+    /// reference counts against the keys pointing at each security key.
+    fn check_security_keys<Rs: Read+Seek>(&mut self, reader: &mut Rs, start_offset: u32, key_security: &[(u32, String)]) {
+        let mut security_keys = match SecurityKeyList::read(reader, start_offset) {
+            Ok(security_keys) => security_keys,
+            Err(error) => {
+                self.push(
+                    IssueKind::SecurityKeyListBroken, start_offset as u64 + HBIN_START_OFFSET, "",
+                    format!("{}", error)
+                );
+                return;
+            }
+        };
+
+        for &(offset, ref path) in key_security {
+            if !security_keys.count_key(path, offset) {
+                self.push(
+                    IssueKind::SecurityKeyListBroken, offset as u64 + HBIN_START_OFFSET, path,
+                    format!("Key points at a security key at offset {} that is not on the security key list.",
+                        offset as u64 + HBIN_START_OFFSET
+                    )
+                );
+            }
+        }
+
+        for mismatch in security_keys.reference_count_mismatches() {
+            self.push(
+                IssueKind::ReferenceCountMismatch, mismatch.offset as u64 + HBIN_START_OFFSET, "",
+                format!("Security key has a reference count of {} but {} keys point at it.",
+                    mismatch.reference_count, mismatch.key_count
+                )
+            );
+        }
+    }
+
+    /// Record that a key references a cell. Returns false when the cell was already referenced.
+    fn reference(&mut self, referenced: &mut HashMap<u32,String>, offset: u32, path: &str)->bool {
+        if let Some(owner) = referenced.get(&offset) {
+            self.push(
+                IssueKind::CellReferencedTwice, offset as u64 + HBIN_START_OFFSET, path,
+                format!("Cell at offset {} is already referenced under {}.",
+                    offset as u64 + HBIN_START_OFFSET, owner
+                )
+            );
+            return false;
+        }
+
+        referenced.insert(offset, path.to_string());
+        true
+    }
+
+    /// Check the subkey list of a key and return the offsets of its subkeys.
+    fn check_sub_key_lists<Rs: Read+Seek>(&mut self, reader: &mut Rs, referenced: &mut HashMap<u32,String>, key_offset: u32, path: &str, nk: &NodeKey, version: &HiveVersion)->Vec<u32> {
+        let list_offset = nk.get_sub_key_list_offset();
+        let mut sub_key_offsets = Vec::new();
+
+        if list_offset != 4294967295 && self.reference(referenced, list_offset, path) {
+            let mut list_offsets = vec![list_offset];
+            while let Some(list_offset) = list_offsets.pop() {
+                let cell_data = Cell::at_offset(reader, list_offset as u64 + HBIN_START_OFFSET)
+                    .and_then(|cell| cell.get_data());
+                if let Ok(ref cell_data) = cell_data {
+                    if let Err(error) = version.check_sub_key_list(cell_data, list_offset as u64 + HBIN_START_OFFSET) {
+                        self.push(
+                            IssueKind::VersionMismatch, list_offset as u64 + HBIN_START_OFFSET, path,
+                            error.message
+                        );
+                    }
+                }
+                match cell_data {
+                    Ok(CellData::FastLeaf(lf)) => sub_key_offsets.extend(lf.get_offsets()),
+                    Ok(CellData::HashLeaf(lh)) => sub_key_offsets.extend(lh.get_offsets()),
+                    Ok(CellData::IndexLeaf(li)) => sub_key_offsets.extend(li.get_offsets()),
+                    Ok(CellData::RootIndex(ref ri)) if list_offset == nk.get_sub_key_list_offset() => {
+                        for sub_list_offset in ri.get_offsets().into_iter().rev() {
+                            if self.reference(referenced, sub_list_offset, path) {
+                                list_offsets.push(sub_list_offset);
+                            }
+                        }
+                    },
+                    Ok(other) => {
+                        self.push(
+                            IssueKind::UnreadableCell, list_offset as u64 + HBIN_START_OFFSET, path,
+                            format!("Expected a subkey list, found {}.", other.get_type_name())
+                        );
+                    },
+                    Err(error) => {
+                        self.push(
+                            IssueKind::UnreadableCell, list_offset as u64 + HBIN_START_OFFSET, path,
+                            format!("{}", error)
+                        );
+                    }
+                }
+            }
+        }
+
+        if sub_key_offsets.len() != nk.get_num_sub_keys() as usize {
+            self.push(
+                IssueKind::SubKeyCountMismatch, key_offset as u64 + HBIN_START_OFFSET, path,
+                format!("Key has {} subkeys but its subkey list holds {}.",
+                    nk.get_num_sub_keys(), sub_key_offsets.len()
+                )
+            );
+        }
+
+        sub_key_offsets
+    }
+
+    /// Check the value list of a key along with its value keys and their data cells.
+    fn check_values<Rs: Read+Seek>(&mut self, reader: &mut Rs, referenced: &mut HashMap<u32,String>, key_offset: u32, path: &str, nk: &NodeKey) {
+        let list_offset = nk.get_value_list_offset();
+        if nk.get_num_values() == 0 {
+            return;
+        }
+        if list_offset == 4294967295 {
+            self.push(
+                IssueKind::ValueCountMismatch, key_offset as u64 + HBIN_START_OFFSET, path,
+                format!("Key has {} values but no value list.", nk.get_num_values())
+            );
+            return;
+        }
+        if !self.reference(referenced, list_offset, path) {
+            return;
+        }
+
+        let cell = match Cell::at_offset(reader, list_offset as u64 + HBIN_START_OFFSET) {
+            Ok(cell) => cell,
+            Err(error) => {
+                self.push(
+                    IssueKind::UnreadableCell, list_offset as u64 + HBIN_START_OFFSET, path,
+                    format!("{}", error)
+                );
+                return;
+            }
+        };
+
+        let mut value_count = nk.get_num_values();
+        if ((cell.data.len() / 4) as u32) < value_count {
+            value_count = (cell.data.len() / 4) as u32;
+            self.push(
+                IssueKind::ValueCountMismatch, key_offset as u64 + HBIN_START_OFFSET, path,
+                format!("Key has {} values but its value list only has room for {}.",
+                    nk.get_num_values(), value_count
+                )
+            );
+        }
+
+        let value_key_list = match ValueKeyList::new(&cell.data, value_count, list_offset as u64) {
+            Ok(value_key_list) => value_key_list,
+            Err(error) => {
+                self.push(
+                    IssueKind::UnreadableCell, cell.get_offset(), path,
+                    format!("{}", error)
+                );
+                return;
+            }
+        };
+
+        for &value_offset in value_key_list.get_offsets() {
+            if !self.reference(referenced, value_offset, path) {
+                continue;
+            }
+
+            let cell_data = Cell::at_offset(reader, value_offset as u64 + HBIN_START_OFFSET)
+                .and_then(|cell| cell.get_data());
+            let vk = match cell_data {
+                Ok(CellData::ValueKey(vk)) => vk,
+                Ok(other) => {
+                    self.push(
+                        IssueKind::UnreadableCell, value_offset as u64 + HBIN_START_OFFSET, path,
+                        format!("Expected a vk, found {}.", other.get_type_name())
+                    );
+                    continue;
+                },
+                Err(error) => {
+                    self.push(
+                        IssueKind::UnreadableCell, value_offset as u64 + HBIN_START_OFFSET, path,
+                        format!("{}", error)
+                    );
+                    continue;
+                }
+            };
+
+            if !vk.data_is_resident() && vk.get_size() > 0 && vk.get_data_offset() != 4294967295 {
+                self.reference(referenced, vk.get_data_offset(), path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hive::Hive;
+    use errors::ErrorKind;
+    use std::io::Cursor;
+    use std::fs::File;
+
+    #[test]
+    fn verify_damaged_hive() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+
+        // Root key cell at 4128 now runs past its 4096 byte hive bin
+        LittleEndian::write_i32(&mut buffer[4128..4132], -8192);
+        // Root key claims 14 subkeys
+        LittleEndian::write_u32(&mut buffer[4152..4156], 14);
+
+        // The root key's lf list: the first subkey no longer points back at the
+        // root key and the second subkey entry is a copy of the first
+        let list = 2587704 + 4096 + 4;
+        let first_sub_key = LittleEndian::read_u32(&buffer[list + 4..list + 8]) as usize;
+        LittleEndian::write_u32(&mut buffer[first_sub_key + 4096 + 4 + 16..first_sub_key + 4096 + 4 + 20], 0);
+        let first_element = buffer[list + 4..list + 12].to_vec();
+        buffer[list + 12..list + 20].copy_from_slice(&first_element);
+
+        // The second hive bin loses its signature
+        buffer[8192] = 0;
+
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+        let report = hive.verify();
+
+        assert_eq!(report.count(IssueKind::DirtySequenceNumbers), 1);
+        assert_eq!(report.count(IssueKind::CellCrossesHiveBin), 1);
+        assert_eq!(report.count(IssueKind::HiveBinError), 1);
+        assert_eq!(report.count(IssueKind::SubKeyCountMismatch), 1);
+        assert_eq!(report.count(IssueKind::ParentMismatch), 1);
+        assert_eq!(report.count(IssueKind::CellReferencedTwice), 1);
+
+        let referenced_twice = report.issues.iter()
+            .find(|issue| issue.kind == IssueKind::CellReferencedTwice)
+            .unwrap();
+        assert_eq!(referenced_twice.offset, first_sub_key as u64 + 4096);
+        assert_eq!(referenced_twice.path, "\\CsiTool-CreateHive-{00000000-0000-0000-0000-000000000000}");
+    }
+
+    #[test]
+    fn verify_version_mismatch() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+
+        // Version 1.7 is unknown, so it is read as the latest version and reported
+        LittleEndian::write_u32(&mut buffer[24..28], 7);
+        let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+        assert_eq!(hive.get_version(), HiveVersion::latest());
+        assert!(hive.keys().all(|result| result.is_ok()));
+        let report = hive.verify();
+        assert_eq!(report.count(IssueKind::VersionMismatch), 1);
+        assert_eq!(report.issues.iter().find(|issue| issue.kind == IssueKind::VersionMismatch).unwrap().offset, 20);
+
+        // Version 1.2 has no lf lists
+        LittleEndian::write_u32(&mut buffer[24..28], 2);
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+
+        let mut keys = hive.keys();
+        assert!(keys.next().unwrap().is_ok());
+        assert_eq!(keys.next().unwrap().err().unwrap().kind, ErrorKind::VersionMismatch);
+        assert_eq!(hive.open_key("Software").err().unwrap().kind, ErrorKind::VersionMismatch);
+
+        let report = hive.verify();
+        assert!(report.count(IssueKind::VersionMismatch) > 1);
+        assert_eq!(report.count(IssueKind::UnreadableCell), 0);
+    }
+
+    #[test]
+    fn verify_security_keys() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).unwrap();
+
+        let report = Hive::from_source(Cursor::new(buffer.clone())).unwrap().verify();
+        assert_eq!(report.count(IssueKind::SecurityKeyListBroken), 0);
+        assert_eq!(report.count(IssueKind::ReferenceCountMismatch), 0);
+
+        // The security key at 672 is used by 84 keys
+        let sk = 4096 + 672 + 4;
+        LittleEndian::write_u32(&mut buffer[sk + 12..sk + 16], 85);
+        let hive = Hive::from_source(Cursor::new(buffer.clone())).unwrap();
+        let report = hive.verify();
+        assert_eq!(report.count(IssueKind::ReferenceCountMismatch), 1);
+        assert_eq!(report.issues.iter().find(|issue| issue.kind == IssueKind::ReferenceCountMismatch).unwrap().offset, 4096 + 672);
+        assert_eq!(hive.security_keys().unwrap().reference_count_mismatches().len(), 1);
+
+        // A key pointing at the value key at 4680 instead of a security key
+        let mut unlisted = buffer.clone();
+        let (path, nk) = Hive::from_source(Cursor::new(buffer.clone())).unwrap().keys().nth(1).unwrap().unwrap();
+        let nk_offset = nk.get_offset() as usize;
+        LittleEndian::write_u32(&mut unlisted[nk_offset + 44..nk_offset + 48], 4680 - 4096);
+        let hive = Hive::from_source(Cursor::new(unlisted)).unwrap();
+        let security_keys = hive.security_keys().unwrap();
+        assert_eq!(security_keys.unlisted_keys().len(), 1);
+        assert_eq!(security_keys.unlisted_keys()[0].path, path);
+        assert_eq!(security_keys.unlisted_keys()[0].security_id, 4680 - 4096);
+
+        // The security key at 672 no longer links to the one of the root key
+        LittleEndian::write_u32(&mut buffer[sk + 8..sk + 12], 129608);
+        let hive = Hive::from_source(Cursor::new(buffer)).unwrap();
+        let report = hive.verify();
+        assert_eq!(report.count(IssueKind::SecurityKeyListBroken), 1);
+        assert_eq!(report.count(IssueKind::ReferenceCountMismatch), 0);
+        assert!(hive.security_keys().is_err());
+    }
+}
@@ -3,7 +3,11 @@ use errors::RegError;
 use hive::HBIN_START_OFFSET;
 use cell::Cell;
 use cell::CellData;
+use db::{DataBlock,DB_SEGMENT_SIZE};
+use baseblock::HiveVersion;
 use utils;
+use resource;
+use resource::{FullResourceDescriptor,ResourceRequirementsList};
 use serde::ser::{SerializeStruct};
 use serde::ser;
 use std::fmt;
@@ -11,12 +15,38 @@ use std::io::Read;
 use std::io::Seek;
 use std::mem::transmute;
 
-#[derive(Serialize, Debug, Clone)]
-#[serde(untagged)]
+#[derive(Debug, Clone)]
 pub enum Data {
     None,
     String(String),
-    Int32(i32),
+    UInt32(u32),
+    UInt64(u64),
+    MultiString(Vec<String>),
+    /// The target of a symbolic link key
+    Link(String),
+    /// Serialized as a hex string
+    Binary(Vec<u8>),
+    ResourceList(Vec<FullResourceDescriptor>),
+    FullResourceDescriptor(FullResourceDescriptor),
+    ResourceRequirementsList(ResourceRequirementsList),
+}
+impl ser::Serialize for Data {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        match *self {
+            Data::None => serializer.serialize_unit(),
+            Data::String(ref value) => serializer.serialize_str(value),
+            Data::UInt32(value) => serializer.serialize_u32(value),
+            Data::UInt64(value) => serializer.serialize_u64(value),
+            Data::MultiString(ref value) => ser::Serialize::serialize(value, serializer),
+            Data::Link(ref value) => serializer.serialize_str(value),
+            Data::Binary(ref value) => serializer.serialize_str(&utils::to_hex_string(value)),
+            Data::ResourceList(ref value) => ser::Serialize::serialize(value, serializer),
+            Data::FullResourceDescriptor(ref value) => ser::Serialize::serialize(value, serializer),
+            Data::ResourceRequirementsList(ref value) => ser::Serialize::serialize(value, serializer)
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -28,14 +58,21 @@ pub struct ValueKeyList{
 }
 impl ValueKeyList{
     pub fn new(buffer: &[u8], value_count: u32, offset: u64) -> Result<ValueKeyList,RegError> {
+        if (buffer.len() / 4) < value_count as usize {
+            return Err(
+                RegError::size_mismatch(
+                    format!("ValueKeyList at offset {} has {} values but only {} bytes.",
+                        offset, value_count, buffer.len()
+                    )
+                )
+            );
+        }
+
         let mut value_offsets: Vec<u32> = Vec::new();
         let next_index: usize = 0;
 
-        for i in 0..value_count {
-            let o = (i*4) as usize;
-            if o+4 > buffer.len(){
-                panic!("error: value_count: {}; buffer: {:?}",value_count,buffer)
-            }
+        for i in 0..value_count as usize {
+            let o = i*4;
             let offset = LittleEndian::read_u32(&buffer[o..o+4]);
             value_offsets.push(offset);
         }
@@ -49,26 +86,63 @@ impl ValueKeyList{
         )
     }
 
+    /// The offsets of the value keys in this list.
+    pub fn get_offsets(&self)->&Vec<u32>{
+        &self.value_offsets
+    }
+
     pub fn get_next_value<Rs: Read+Seek>(&mut self, reader: &mut Rs)->Result<Option<ValueKey>,RegError>{
         if self.next_index >= self.value_offsets.len(){
             Ok(None)
         }
         else {
             let cell_offset = self.value_offsets[self.next_index] as u64 + HBIN_START_OFFSET;
+            self.next_index += 1;
             match Cell::at_offset(reader, cell_offset)?.get_data()?{
                 CellData::ValueKey(vk)=>{
-                    self.next_index += 1;
                     Ok(Some(vk))
                 },
-                other => panic!("CellData is not type ValueKey: {:?}",other)
+                other => Err(
+                    RegError::unexpected_cell_type(cell_offset, "vk", other.get_type_name())
+                )
             }
         }
     }
 }
 
+/// How binary value data is written when a ValueKey is serialized.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum BinaryEncoding {
+    /// Uppercase hex string (the default)
+    Hex,
+    /// Standard base64 with padding
+    Base64
+}
+
+/// How value data is decoded when a ValueKey is serialized.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum DecodeMode {
+    /// decode_data: data that does not fit its type is an error (the default)
+    Strict,
+    /// decode_data_checked: data that does not fit its type is decoded as far as
+    /// possible and reported as warnings
+    Checked
+}
+
+/// Value data decoded by decode_data_checked. No byte of the value data is dropped:
+/// what is not part of the decoded value ends up in the remnant.
+#[derive(Debug, Clone)]
+pub struct DecodedData {
+    pub data: Option<Data>,
+    /// Bytes after the string terminator or past the size of the data type
+    pub remnant: Vec<u8>,
+    pub warnings: Vec<String>
+}
+
 bitflags! {
     pub struct VkFlags: u16 {
         const VK_VALUE_COMP_NAME = 0x0001;
+        const VK_VALUE_TOMBSTONE = 0x0002;
     }
 }
 impl fmt::Display for VkFlags {
@@ -144,10 +218,16 @@ pub struct ValueKey {
     value_name: String,
     padding: Vec<u8>,
     data: Vec<u8>,
-    data_slack: Vec<u8>
+    data_slack: Vec<u8>,
+    data_slack_offset: u64,
+    binary_encoding: BinaryEncoding,
+    decode_mode: DecodeMode,
+    version: HiveVersion
 }
 impl ValueKey {
     pub fn new(buffer: &[u8], offset: u64)->Result<ValueKey,RegError> {
+        utils::check_len(buffer, 20, "ValueKey", offset)?;
+
         let signature = LittleEndian::read_u16(&buffer[0..2]);
         let value_name_size = LittleEndian::read_u16(&buffer[2..4]);
         let data_size = LittleEndian::read_u32(&buffer[4..8]);
@@ -160,12 +240,22 @@ impl ValueKey {
         );
         let unknown1 = LittleEndian::read_u16(&buffer[18..20]);
 
+        if buffer.len() < 20 + value_name_size as usize {
+            return Err(
+                RegError::validation_error(
+                    format!("ValueKey at offset {} has a value name size of {} but only {} bytes.",
+                        offset, value_name_size, buffer.len()
+                    )
+                )
+            );
+        }
+
         let value_name = match flags.contains(VkFlags::VK_VALUE_COMP_NAME) {
-            true => utils::read_ascii(&buffer[20..(20 + value_name_size) as usize])?,
-            false => utils::read_utf16(&buffer[20..(20 + value_name_size) as usize])?
+            true => utils::read_ascii(&buffer[20..20 + value_name_size as usize])?,
+            false => utils::read_utf16(&buffer[20..20 + value_name_size as usize])?
         };
 
-        let padding = buffer[(20 + value_name_size) as usize..].to_vec();
+        let padding = buffer[20 + value_name_size as usize..].to_vec();
         let data = Vec::new();
         let data_slack = Vec::new();
 
@@ -182,7 +272,11 @@ impl ValueKey {
                 value_name: value_name,
                 padding: padding,
                 data: data,
-                data_slack: data_slack
+                data_slack: data_slack,
+                data_slack_offset: 0,
+                binary_encoding: BinaryEncoding::Hex,
+                decode_mode: DecodeMode::Strict,
+                version: HiveVersion::latest()
             }
         )
     }
@@ -191,6 +285,70 @@ impl ValueKey {
         &self.value_name
     }
 
+    /// The cell offset of the data, or the data itself when it is resident.
+    pub fn get_data_offset(&self)->u32 {
+        self.data_offset
+    }
+
+    pub fn get_data_type(&self)->&VkDataType {
+        &self.data_type
+    }
+
+    /// The numeric data type code, including codes that have no name.
+    pub fn get_raw_data_type(&self)->u32 {
+        self.data_type.as_u32()
+    }
+
+    /// The value data exactly as stored, without decoding. Filled by read_value.
+    pub fn get_raw_data(&self)->&[u8] {
+        &self.data
+    }
+
+    /// Choose how binary data is written when this value is serialized.
+    pub fn set_binary_encoding(&mut self, binary_encoding: BinaryEncoding) {
+        self.binary_encoding = binary_encoding;
+    }
+
+    /// Choose how data is decoded when this value is serialized.
+    pub fn set_decode_mode(&mut self, decode_mode: DecodeMode) {
+        self.decode_mode = decode_mode;
+    }
+
+    /// Set the format version of the hive the value is from. Data of hives before 1.4 is
+    /// never read as a db record. Defaults to the latest version.
+    pub fn set_version(&mut self, version: HiveVersion) {
+        self.version = version;
+    }
+
+    /// A value in a version 1.6 hive can be a tombstone, deleting the value of the same
+    /// name in the layers below it.
+    pub fn is_tombstone(&self)->bool {
+        self.version.has_layered_keys() && self.flags.contains(VkFlags::VK_VALUE_TOMBSTONE)
+    }
+
+    fn encode_binary(&self, bytes: &[u8])->String {
+        match self.binary_encoding {
+            BinaryEncoding::Hex => utils::to_hex_string(bytes),
+            BinaryEncoding::Base64 => utils::to_base64_string(bytes)
+        }
+    }
+
+    /// The bytes of the cell after the value name.
+    pub fn get_padding(&self)->&[u8] {
+        &self.padding
+    }
+
+    /// The bytes of the data cell after the value data. Filled by read_value.
+    pub fn get_data_slack(&self)->&[u8] {
+        &self.data_slack
+    }
+
+    /// The absolute offset of the cell holding the data slack: the data cell, or the
+    /// last segment cell of big data. Filled by read_value.
+    pub fn get_data_slack_offset(&self)->u64 {
+        self.data_slack_offset
+    }
+
     pub fn data_is_resident(&self)->bool {
         if self.data_size >> 31 == 0 {
             false
@@ -214,21 +372,21 @@ impl ValueKey {
             // seek to data value
             let cell = Cell::at_offset(reader, self.data_offset as u64 + HBIN_START_OFFSET)?;
 
-            match cell.get_data()? {
-                CellData::DataBlock(data_block) => {
-                    let data = data_block.get_data(reader)?;
-                    self.data = data;
-                },
-                CellData::Raw(rd) => {
-                    self.data = rd;
-                },
-                other => {
-                    error!("read_value_from_hive unhandled cell data type: {:?}",other);
-                    panic!("read_value_from_hive unhandled cell data type: {:?}",other);
+            // Only data too large for a single segment lives in a db record, and only from
+            // version 1.4 on. Other data is raw and can start with bytes that look like a
+            // signature.
+            if self.version.has_big_data() && self.get_size() > DB_SEGMENT_SIZE &&
+                cell.get_signature().as_u16() == 25188 {
+                let data_block = DataBlock::new(&cell.data, cell.get_offset() + 4)?;
+                let segment_offsets = data_block.get_segment_offsets(reader)?;
+                self.data = data_block.read_segments(reader, &segment_offsets)?;
+                if let Some(&last_offset) = segment_offsets.last() {
+                    self.data_slack_offset = last_offset as u64 + HBIN_START_OFFSET;
                 }
+            } else {
+                self.data_slack_offset = cell.get_offset();
+                self.data = cell.data;
             }
-
-            Ok(true)
         } else {
             let raw_buffer: [u8; 4] = unsafe {
                 transmute(self.data_offset.to_le())
@@ -236,9 +394,38 @@ impl ValueKey {
 
             // set data
             self.data = raw_buffer.to_vec();
+        }
+
+        if (self.data.len() as u32) < self.get_size() {
+            let data_len = self.data.len();
+            self.data = Vec::new();
+            return Err(
+                RegError::validation_error(
+                    format!("ValueKey at offset {} has {} bytes of data but a size of {}.",
+                        self._offset, data_len, self.get_size()
+                    )
+                )
+            );
+        }
+
+        // Anything past the value size is slack
+        let size = self.get_size() as usize;
+        self.data_slack = self.data.split_off(size);
+
+        Ok(true)
+    }
 
-            Ok(true)
+    fn check_data_size(&self, size: u32)->Result<(),RegError>{
+        if self.get_size() < size {
+            return Err(
+                RegError::size_mismatch(
+                    format!("ValueKey at offset {} is a {} with only {} bytes of data.",
+                        self._offset, self.data_type, self.get_size()
+                    )
+                )
+            );
         }
+        Ok(())
     }
 
     pub fn decode_data(&self)->Result<Option<Data>,RegError>{
@@ -247,92 +434,312 @@ impl ValueKey {
         let data_len = self.data.len();
         if data_len > 0 {
             if self.get_size() > data_len as u32 {
-                panic!("Size is greater than data: {} > {}\n{:?}",self.get_size(),data_len,self);
+                return Err(
+                    RegError::size_mismatch(
+                        format!("ValueKey at offset {} has a size of {} but only {} bytes of data.",
+                            self._offset, self.get_size(), data_len
+                        )
+                    )
+                );
             }
 
+            let data = &self.data[0..self.get_size() as usize];
             match self.data_type.0 {
-                0x00000000 => { //REG_NONE
-                    return Ok(None);
-                },
-                0x00000001 => { //REG_SZ
-                    let d_size = self.get_size();
-
-                    if d_size == 0 {
+                0x00000001 | 0x00000002 => { //REG_SZ, REG_EXPAND_SZ
+                    if data.is_empty() {
                         return Ok(None);
                     }
 
-                    let value = utils::read_utf16(
-                        &self.data[0..d_size as usize]
-                    )?;
-
-                    return Ok(
-                        Some(Data::String(value))
-                    );
+                    let value = utils::read_utf16(data)?;
+                    return Ok(Some(Data::String(value)));
                 },
-                0x00000002 => { //REG_EXPAND_SZ
-                    let d_size = self.get_size();
+                0x00000004 => { //REG_DWORD_LITTLE_ENDIAN
+                    self.check_data_size(4)?;
+                    let value = LittleEndian::read_u32(&data[0..4]);
+                    return Ok(Some(Data::UInt32(value)));
+                },
+                0x00000005 => { //REG_DWORD_BIG_ENDIAN
+                    self.check_data_size(4)?;
+                    let value = BigEndian::read_u32(&data[0..4]);
+                    return Ok(Some(Data::UInt32(value)));
+                },
+                0x00000006 => { //REG_LINK
+                    let value = utils::read_utf16(data)?;
+                    return Ok(Some(Data::Link(value)));
+                },
+                0x00000007 => { //REG_MULTI_SZ
+                    let value = utils::read_multi_string(data)?;
+                    return Ok(Some(Data::MultiString(value)));
+                },
+                0x00000008 | 0x00000009 | 0x0000000a => { //resource lists
+                    let decoded = match self.data_type.0 {
+                        0x00000008 => resource::read_resource_list(data).map(Data::ResourceList),
+                        0x00000009 => resource::read_full_resource_descriptor(data).map(Data::FullResourceDescriptor),
+                        _ => ResourceRequirementsList::new(data).map(Data::ResourceRequirementsList)
+                    };
+                    // A malformed resource list is still written out, as binary
+                    return match decoded {
+                        Ok(value) => Ok(Some(value)),
+                        Err(error) => {
+                            debug!("ValueKey at offset {} kept as binary: {}",self._offset,error);
+                            Ok(Some(Data::Binary(data.to_vec())))
+                        }
+                    };
+                },
+                0x0000000b => { //REG_QWORD_LITTLE_ENDIAN
+                    self.check_data_size(8)?;
+                    let value = LittleEndian::read_u64(&data[0..8]);
+                    return Ok(Some(Data::UInt64(value)));
+                },
+                _ => { //REG_NONE, REG_BINARY and unknown types
+                    return Ok(Some(Data::Binary(data.to_vec())));
+                }
+            }
+        }
+        else {
+            return Ok(None);
+        }
+    }
 
-                    if d_size == 0 {
-                        return Ok(None);
+    /// Decode the data without failing. Type and size mismatches are reported as
+    /// warnings, strings stored as ASCII are decoded as ASCII, and bytes that are not
+    /// part of the decoded value are kept as the remnant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use rwinreg::cell::Cell;
+    /// use rwinreg::cell::CellData;
+    /// use rwinreg::vk::Data;
+    ///
+    /// # fn test_decode_data_checked() {
+    /// let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+    /// let mut vk = match Cell::at_offset(&mut file, 4680).unwrap().get_data().unwrap() {
+    ///     CellData::ValueKey(vk) => vk,
+    ///     other => panic!("{:?}",other)
+    /// };
+    /// vk.read_value(&mut file).unwrap();
+    ///
+    /// let decoded = vk.decode_data_checked();
+    /// match decoded.data {
+    ///     Some(Data::String(ref value)) => assert!(value.starts_with("Mozilla/")),
+    ///     ref other => panic!("{:?}",other)
+    /// }
+    /// assert!(decoded.remnant.is_empty());
+    /// assert!(decoded.warnings.is_empty());
+    /// # }
+    /// # test_decode_data_checked();
+    /// ```
+    pub fn decode_data_checked(&self)->DecodedData{
+        let mut warnings = Vec::new();
+
+        let size = self.get_size() as usize;
+        let data = if size > self.data.len() {
+            warnings.push(
+                format!("{} has a size of {} but only {} bytes of data.",
+                    self.data_type, size, self.data.len()
+                )
+            );
+            &self.data[..]
+        } else {
+            &self.data[0..size]
+        };
+
+        if data.is_empty() {
+            return DecodedData {
+                data: None,
+                remnant: Vec::new(),
+                warnings: warnings
+            };
+        }
+
+        let (value, remnant) = match self.data_type.0 {
+            0x00000001 | 0x00000002 => { //REG_SZ, REG_EXPAND_SZ
+                let (value, remnant) = self.decode_string_checked(data, &mut warnings);
+                (Some(Data::String(value)), remnant)
+            },
+            0x00000004 | 0x00000005 => { //REG_DWORD_LITTLE_ENDIAN, REG_DWORD_BIG_ENDIAN
+                match self.check_fixed_size(data, 4, &mut warnings) {
+                    true => {
+                        let value = match self.data_type.0 {
+                            0x00000004 => LittleEndian::read_u32(&data[0..4]),
+                            _ => BigEndian::read_u32(&data[0..4])
+                        };
+                        (Some(Data::UInt32(value)), data[4..].to_vec())
+                    },
+                    false => (Some(Data::Binary(data.to_vec())), Vec::new())
+                }
+            },
+            0x00000006 => { //REG_LINK
+                let (value, remnant) = self.decode_string_checked(data, &mut warnings);
+                (Some(Data::Link(value)), remnant)
+            },
+            0x00000007 => { //REG_MULTI_SZ
+                let (value, remnant) = self.decode_multi_string_checked(data, &mut warnings);
+                (Some(Data::MultiString(value)), remnant)
+            },
+            0x0000000b => { //REG_QWORD_LITTLE_ENDIAN
+                match self.check_fixed_size(data, 8, &mut warnings) {
+                    true => (Some(Data::UInt64(LittleEndian::read_u64(&data[0..8]))), data[8..].to_vec()),
+                    false => (Some(Data::Binary(data.to_vec())), Vec::new())
+                }
+            },
+            0x00000008 | 0x00000009 | 0x0000000a => { //resource lists
+                let decoded = match self.data_type.0 {
+                    0x00000008 => resource::read_resource_list(data).map(Data::ResourceList),
+                    0x00000009 => resource::read_full_resource_descriptor(data).map(Data::FullResourceDescriptor),
+                    _ => ResourceRequirementsList::new(data).map(Data::ResourceRequirementsList)
+                };
+                match decoded {
+                    Ok(value) => (Some(value), Vec::new()),
+                    Err(error) => {
+                        warnings.push(format!("{}", error));
+                        (Some(Data::Binary(data.to_vec())), Vec::new())
                     }
+                }
+            },
+            _ => { //REG_NONE, REG_BINARY and unknown types
+                (Some(Data::Binary(data.to_vec())), Vec::new())
+            }
+        };
 
-                    let value = utils::read_utf16(
-                        &self.data[0..d_size as usize]
-                    )?;
+        DecodedData {
+            data: value,
+            remnant: remnant,
+            warnings: warnings
+        }
+    }
 
-                    return Ok(
-                        Some(Data::String(value))
-                    );
-                },
-                0x00000003 => { //REG_BINARY
-                    let value = utils::to_hex_string(
-                        &self.data
-                    );
-                    return Ok(Some(Data::String(value)));
+    /// A DWORD or QWORD needs at least its own size. Returns false when it is too short.
+    fn check_fixed_size(&self, data: &[u8], size: usize, warnings: &mut Vec<String>)->bool{
+        if data.len() != size {
+            warnings.push(
+                format!("{} has {} bytes of data instead of {}.", self.data_type, data.len(), size)
+            );
+        }
+        data.len() >= size
+    }
+
+    /// Decode a string up to its first null. Returns the string and the bytes after the null.
+    fn decode_string_checked(&self, data: &[u8], warnings: &mut Vec<String>)->(String,Vec<u8>){
+        if utils::is_ascii_text(data) {
+            warnings.push(
+                format!("{} data is stored as ASCII.", self.data_type)
+            );
+            return match data.iter().position(|&b| b == 0) {
+                Some(end) => (String::from_utf8_lossy(&data[0..end]).into_owned(), data[end + 1..].to_vec()),
+                None => {
+                    warnings.push(format!("{} data is not null terminated.", self.data_type));
+                    (String::from_utf8_lossy(data).into_owned(), Vec::new())
                 }
-                0x00000004 => { //REG_DWORD_LITTLE_ENDIAN
-                    let value = LittleEndian::read_i32(
-                        &self.data[0..4]
-                    );
+            };
+        }
 
-                    return Ok(Some(Data::Int32(value)));
-                },
-                0x00000005 => { //REG_DWORD_BIG_ENDIAN
-                    let value = BigEndian::read_i32(
-                        &self.data[0..4]
-                    );
+        let (end, remnant_start) = match utils::find_utf16_null(data) {
+            Some(end) => (end, end + 2),
+            None => {
+                warnings.push(format!("{} data is not null terminated.", self.data_type));
+                if data.len() % 2 != 0 {
+                    warnings.push(format!("{} data has an odd length of {}.", self.data_type, data.len()));
+                }
+                (data.len() - data.len() % 2, data.len() - data.len() % 2)
+            }
+        };
+
+        let (value, valid) = utils::read_utf16_lossy(&data[0..end]);
+        if !valid {
+            warnings.push(format!("{} data is not valid UTF-16.", self.data_type));
+        }
 
-                    return Ok(Some(Data::Int32(value)));
+        (value, data[remnant_start..].to_vec())
+    }
+
+    /// Decode strings up to the empty string that ends the list. Returns the strings and
+    /// the bytes after the list.
+    fn decode_multi_string_checked(&self, data: &[u8], warnings: &mut Vec<String>)->(Vec<String>,Vec<u8>){
+        let mut strings = Vec::new();
+        let mut start = 0;
+        let mut terminated = false;
+        let mut valid = true;
+
+        while start + 1 < data.len() {
+            let (end, next_start) = match utils::find_utf16_null(&data[start..]) {
+                Some(0) => {
+                    terminated = true;
+                    start += 2;
+                    break;
                 },
-                _ => {
-                    let value = utils::to_hex_string(
-                        &self.data[0..self.get_size() as usize]
-                    );
-                    return Ok(Some(Data::String(value)));
+                Some(end) => (start + end, start + end + 2),
+                None => {
+                    let end = data.len() - (data.len() - start) % 2;
+                    (end, end)
                 }
-            }
+            };
+
+            let (value, value_valid) = utils::read_utf16_lossy(&data[start..end]);
+            valid = valid && value_valid;
+            strings.push(value);
+            start = next_start;
         }
-        else {
-            return Ok(None);
+
+        if !terminated {
+            warnings.push(format!("{} data does not end with an empty string.", self.data_type));
         }
+        if !valid {
+            warnings.push(format!("{} data is not valid UTF-16.", self.data_type));
+        }
+
+        (strings, data[start..].to_vec())
     }
 }
 impl ser::Serialize for ValueKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: ser::Serializer
     {
-        let mut state = serializer.serialize_struct("ValueKey", 5)?;
+        let mut error = None;
+        let decoded = match self.decode_mode {
+            DecodeMode::Strict => {
+                match self.decode_data() {
+                    Ok(data) => DecodedData { data: data, remnant: Vec::new(), warnings: Vec::new() },
+                    Err(decode_error) => {
+                        // Keep the value with its raw data and why it did not decode
+                        error = Some(format!("{}",decode_error));
+                        DecodedData { data: Some(Data::Binary(self.data.clone())), remnant: Vec::new(), warnings: Vec::new() }
+                    }
+                }
+            },
+            DecodeMode::Checked => self.decode_data_checked()
+        };
+
+        // The five fields always written, and those written only when set
+        let field_count = 5 +
+            (!decoded.remnant.is_empty()) as usize +
+            (!decoded.warnings.is_empty()) as usize +
+            (error.is_some()) as usize;
+
+        let mut state = serializer.serialize_struct("ValueKey", field_count)?;
         state.serialize_field("data_size", &self.data_size)?;
         state.serialize_field("data_type", &self.data_type)?;
         state.serialize_field("flags", &self.flags)?;
         state.serialize_field("value_name", &self.value_name)?;
-        let data = match self.decode_data() {
-            Ok(data)=>data,
-            Err(error)=>{
-                panic!("{:?}",error)
+        match decoded.data {
+            Some(Data::Binary(ref value)) => {
+                state.serialize_field("data", &self.encode_binary(value))?;
+            },
+            ref data => {
+                state.serialize_field("data", data)?;
             }
-        };
-        state.serialize_field("data", &data)?;
+        }
+        if !decoded.remnant.is_empty() {
+            state.serialize_field("remnant", &self.encode_binary(&decoded.remnant))?;
+        }
+        if !decoded.warnings.is_empty() {
+            state.serialize_field("warnings", &decoded.warnings)?;
+        }
+        if let Some(ref error) = error {
+            state.serialize_field("error", error)?;
+        }
         state.end()
     }
 }
@@ -340,6 +747,8 @@ impl ser::Serialize for ValueKey {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use errors::ErrorKind;
+    use serde_json;
     use cell::Cell;
     use std::io::Cursor;
     use std::io::Read;
@@ -379,4 +788,206 @@ mod tests {
         ];
         assert_eq!(&vk.padding[..], known_data);
     }
+
+    #[test]
+    fn valuekey_read_value() {
+        let mut file = File::open(".testdata/NTUSER.DAT").unwrap();
+
+        let cell = match Cell::at_offset(&mut file, 4680){
+            Ok(cell)=>cell,
+            Err(error)=>panic!("{:?}",error)
+        };
+
+        let mut vk = match ValueKey::new(&cell.data,4684){
+            Ok(vk)=>vk,
+            Err(error)=>panic!("{:?}",error)
+        };
+
+        match vk.read_value(&mut file){
+            Err(error)=>panic!("{:?}",error),
+            _ => {}
+        }
+
+        assert_eq!(vk.data.len(), 84);
+        assert_eq!(vk.data_slack.len(), 0);
+        match vk.decode_data(){
+            Ok(Some(Data::String(value)))=>assert!(value.starts_with("Mozilla/")),
+            other=>panic!("{:?}",other)
+        }
+    }
+
+    #[test]
+    fn valuekey_read_big_data() {
+        // A db cell, its segment list and two segments, after an empty base block
+        let mut buffer = vec![0; 4096 + 16384 + 32];
+        LittleEndian::write_i32(&mut buffer[4096..4100], -16);
+        buffer[4100..4102].copy_from_slice(b"db");
+        LittleEndian::write_u16(&mut buffer[4102..4104], 2);
+        LittleEndian::write_u32(&mut buffer[4104..4108], 16);
+        LittleEndian::write_i32(&mut buffer[4112..4116], -16);
+        LittleEndian::write_u32(&mut buffer[4116..4120], 32);
+        LittleEndian::write_u32(&mut buffer[4120..4124], 16384);
+        LittleEndian::write_i32(&mut buffer[4128..4132], -16352);
+        LittleEndian::write_i32(&mut buffer[4096 + 16384..4096 + 16388], -32);
+
+        // The last segment holds 20 bytes of data and 8 bytes of slack
+        let mut vk = value_with_data(0x00000003, &[]);
+        vk.data_size = DB_SEGMENT_SIZE + 20;
+        vk.data_offset = 0;
+        vk.read_value(&mut Cursor::new(buffer)).unwrap();
+
+        assert_eq!(vk.get_raw_data().len(), DB_SEGMENT_SIZE as usize + 20);
+        assert_eq!(vk.get_data_slack().len(), 8);
+        assert_eq!(vk.get_data_slack_offset(), 4096 + 16384);
+    }
+
+    /// A value key with the given type and data, as if its data had been read.
+    fn value_with_data(data_type: u32, data: &[u8])->ValueKey {
+        let mut buffer = vec![0; 20];
+        buffer[0..2].copy_from_slice(b"vk");
+        LittleEndian::write_u32(&mut buffer[4..8], data.len() as u32);
+        LittleEndian::write_u32(&mut buffer[12..16], data_type);
+
+        let mut vk = ValueKey::new(&buffer,4).unwrap();
+        vk.data = data.to_vec();
+        vk
+    }
+
+    #[test]
+    fn valuekey_decode_data() {
+        let vk = value_with_data(0x00000004, &[0xFF,0xFF,0xFF,0xFF]);
+        assert_eq!(serde_json::to_string(&vk.decode_data().unwrap()).unwrap(), "4294967295");
+
+        let vk = value_with_data(0x00000005, &[0x00,0x00,0x01,0x00]);
+        assert_eq!(serde_json::to_string(&vk.decode_data().unwrap()).unwrap(), "256");
+
+        let vk = value_with_data(0x0000000b, &[0x00,0x00,0x00,0x00,0x01,0x00,0x00,0x00]);
+        assert_eq!(serde_json::to_string(&vk.decode_data().unwrap()).unwrap(), "4294967296");
+
+        let vk = value_with_data(0x00000007, &[0x41,0x00,0x00,0x00,0x42,0x00,0x00,0x00,0x00,0x00]);
+        assert_eq!(serde_json::to_string(&vk.decode_data().unwrap()).unwrap(), r#"["A","B"]"#);
+
+        let vk = value_with_data(0x00000006, &[0x5C,0x00,0x41,0x00]);
+        match vk.decode_data() {
+            Ok(Some(Data::Link(value))) => assert_eq!(value, "\\A"),
+            other => panic!("{:?}",other)
+        }
+
+        let vk = value_with_data(0x00000000, &[0x01,0xAB]);
+        assert_eq!(serde_json::to_string(&vk.decode_data().unwrap()).unwrap(), r#""01AB""#);
+
+        let vk = value_with_data(0x0000000b, &[0x01,0x00,0x00,0x00]);
+        match vk.decode_data() {
+            Err(RegError { kind: ErrorKind::SizeMismatch, .. }) => {},
+            other => panic!("{:?}",other)
+        }
+        // The value is still serialized, with its raw data and the error
+        let json = serde_json::to_string(&vk).unwrap();
+        assert!(json.contains(r#""data":"01000000","error":"SizeMismatch: "#));
+    }
+
+    #[test]
+    fn valuekey_raw_data() {
+        let mut vk = value_with_data(0x00000003, &[0xDE,0xAD,0xBE,0xEF]);
+        assert_eq!(vk.get_raw_data(), &[0xDE,0xAD,0xBE,0xEF]);
+        assert_eq!(vk.get_raw_data_type(), 3);
+        assert!(serde_json::to_string(&vk).unwrap().ends_with(r#""data":"DEADBEEF"}"#));
+
+        vk.set_binary_encoding(BinaryEncoding::Base64);
+        assert!(serde_json::to_string(&vk).unwrap().ends_with(r#""data":"3q2+7w=="}"#));
+    }
+
+    #[test]
+    fn valuekey_decode_resource_list_truncated() {
+        // A resource list claiming one full resource descriptor but holding none
+        let vk = value_with_data(0x00000008, &[0x01,0x00,0x00,0x00]);
+        match vk.decode_data() {
+            Ok(Some(Data::Binary(ref value))) => assert_eq!(value, &vec![0x01,0x00,0x00,0x00]),
+            other => panic!("{:?}",other)
+        }
+        assert!(serde_json::to_string(&vk).unwrap().ends_with(r#""data":"01000000"}"#));
+    }
+
+    #[test]
+    fn valuekey_checked_string() {
+        // UTF-16 with data after the terminator
+        let vk = value_with_data(0x00000001, &[0x41,0x00,0x00,0x00,0x42,0x00,0x43]);
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::String(ref value)) => assert_eq!(value, "A"),
+            ref other => panic!("{:?}",other)
+        }
+        assert_eq!(decoded.remnant, vec![0x42,0x00,0x43]);
+        assert!(decoded.warnings.is_empty());
+
+        // ASCII stored as REG_SZ
+        let vk = value_with_data(0x00000002, b"%SystemRoot%\0\xFF");
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::String(ref value)) => assert_eq!(value, "%SystemRoot%"),
+            ref other => panic!("{:?}",other)
+        }
+        assert_eq!(decoded.remnant, vec![0xFF]);
+        assert_eq!(decoded.warnings.len(), 1);
+
+        // CJK text whose bytes are also printable ASCII
+        let vk = value_with_data(0x00000001, &[0x41,0x61,0x00,0x00]);
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::String(ref value)) => assert_eq!(value, "\u{6141}"),
+            ref other => panic!("{:?}",other)
+        }
+        assert!(decoded.warnings.is_empty());
+
+        // Odd length without a terminator keeps the last byte
+        let vk = value_with_data(0x00000001, &[0x41,0x00,0x42,0x00,0x43]);
+        let decoded = vk.decode_data_checked();
+        assert_eq!(decoded.remnant, vec![0x43]);
+        assert_eq!(decoded.warnings.len(), 2);
+    }
+
+    #[test]
+    fn valuekey_checked_dword() {
+        let vk = value_with_data(0x00000004, &[0x01,0x02]);
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::Binary(ref value)) => assert_eq!(value, &vec![0x01,0x02]),
+            ref other => panic!("{:?}",other)
+        }
+        assert_eq!(decoded.warnings.len(), 1);
+
+        let vk = value_with_data(0x00000004, &[0x01,0x00,0x00,0x00,0x05]);
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::UInt32(1)) => {},
+            ref other => panic!("{:?}",other)
+        }
+        assert_eq!(decoded.remnant, vec![0x05]);
+    }
+
+    #[test]
+    fn valuekey_checked_multi_string() {
+        // A size past the data
+        let mut vk = value_with_data(0x00000007, &[0x41,0x00,0x00,0x00,0x00,0x00,0x09]);
+        vk.data_size = 16;
+        let decoded = vk.decode_data_checked();
+        match decoded.data {
+            Some(Data::MultiString(ref value)) => assert_eq!(value, &vec!["A".to_string()]),
+            ref other => panic!("{:?}",other)
+        }
+        assert_eq!(decoded.remnant, vec![0x09]);
+        assert_eq!(decoded.warnings.len(), 1);
+
+        vk.set_decode_mode(DecodeMode::Checked);
+        let json = serde_json::to_string(&vk).unwrap();
+        assert!(json.contains(r#""data":["A"],"remnant":"09","warnings":["#));
+    }
+
+    #[test]
+    fn valuekeylist_short_buffer() {
+        match ValueKeyList::new(&[0x20,0x01,0x00,0x00], 2, 4096) {
+            Err(RegError { kind: ErrorKind::SizeMismatch, .. }) => {},
+            other => panic!("{:?}",other)
+        }
+    }
 }
@@ -0,0 +1,170 @@
+use hive::HBIN_START_OFFSET;
+use std::io::{Read,Seek,SeekFrom,Cursor};
+use std::io;
+
+/// Bit 31 of a cell offset marks a cell in volatile storage. Volatile cells only exist
+/// in memory and are never written to the hive file.
+pub const VOLATILE_CELL_FLAG: u32 = 0x80000000;
+
+/// The absolute offset that volatile cell offsets are mapped to, past any hive file.
+pub const VOLATILE_START_OFFSET: u64 = HBIN_START_OFFSET + VOLATILE_CELL_FLAG as u64;
+
+/// Check if a cell offset points into volatile storage.
+pub fn is_volatile(offset: u32)->bool{
+    offset & VOLATILE_CELL_FLAG != 0
+}
+
+/// The source of a hive carved from memory: the stable storage (base block and hive
+/// bins, as in a hive file) and the volatile storage, its hive bins laid out so that a
+/// volatile cell offset without bit 31 is its position in the buffer.
+///
+/// Absolute offsets from VOLATILE_START_OFFSET on read from volatile storage, so the
+/// usual `offset + HBIN_START_OFFSET` arithmetic reaches volatile cells as well.
+pub struct VolatileSource<Rs> {
+    stable: Rs,
+    volatile: Cursor<Vec<u8>>,
+    in_volatile: bool
+}
+impl <Rs: Read+Seek> VolatileSource<Rs> {
+    pub fn new(stable: Rs, volatile: Vec<u8>) -> VolatileSource<Rs> {
+        VolatileSource {
+            stable: stable,
+            volatile: Cursor::new(volatile),
+            in_volatile: false
+        }
+    }
+
+    fn position(&mut self)->io::Result<u64>{
+        match self.in_volatile {
+            true => Ok(VOLATILE_START_OFFSET + self.volatile.position()),
+            false => self.stable.seek(SeekFrom::Current(0))
+        }
+    }
+}
+impl <Rs: Read+Seek> Read for VolatileSource<Rs> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.in_volatile {
+            true => self.volatile.read(buf),
+            false => self.stable.read(buf)
+        }
+    }
+}
+impl <Rs: Read+Seek> Seek for VolatileSource<Rs> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let absolute = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(_) => {
+                // The end is the end of the stable storage, as for a hive file
+                self.in_volatile = false;
+                return self.stable.seek(pos);
+            },
+            SeekFrom::Current(delta) => {
+                let current = self.position()? as i64;
+                if current + delta < 0 {
+                    return Err(
+                        io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the source")
+                    );
+                }
+                (current + delta) as u64
+            }
+        };
+
+        if absolute >= VOLATILE_START_OFFSET {
+            self.in_volatile = true;
+            self.volatile.seek(SeekFrom::Start(absolute - VOLATILE_START_OFFSET))?;
+        } else {
+            self.in_volatile = false;
+            self.stable.seek(SeekFrom::Start(absolute))?;
+        }
+        Ok(absolute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hive::Hive;
+    use errors::ErrorKind;
+    use std::fs::File;
+
+    #[test]
+    fn volatile_source_seek() {
+        let mut source = VolatileSource::new(
+            Cursor::new(vec![1u8, 2, 3, 4]),
+            vec![5u8, 6, 7, 8]
+        );
+        let mut buffer = [0u8; 2];
+
+        source.seek(SeekFrom::Start(VOLATILE_START_OFFSET + 1)).unwrap();
+        source.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [6, 7]);
+
+        assert_eq!(source.seek(SeekFrom::Current(0)).unwrap(), VOLATILE_START_OFFSET + 3);
+
+        source.seek(SeekFrom::Start(2)).unwrap();
+        source.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [3, 4]);
+
+        assert!(is_volatile(0x80000268));
+        assert!(!is_volatile(0x268));
+    }
+
+    #[test]
+    fn hive_from_memory() {
+        let mut stable = Vec::new();
+        File::open(".testdata/NTUSER.DAT").unwrap().read_to_end(&mut stable).unwrap();
+
+        // Without volatile storage the volatile lists of 10 keys are noted and skipped
+        let hive = Hive::from_source(Cursor::new(stable.clone())).unwrap();
+        let mut keys = hive.keys();
+        let key_count = keys.by_ref().map(|result| result.unwrap()).count();
+        let diagnostics = keys.get_diagnostics();
+        assert_eq!(diagnostics.len(), 10);
+        assert!(diagnostics.iter().all(|d| d.kind == ErrorKind::VolatileOffset));
+
+        let root = hive.get_root_node().unwrap();
+        assert_eq!(root.get_volatile_sub_key_list_offset(), 0x80000268);
+        let root_key = hive.get_root_key().unwrap();
+        assert_eq!(root_key.volatile_subkeys().err().unwrap().kind, ErrorKind::VolatileOffset);
+        assert_eq!(root_key.subkeys().unwrap().len(), 13);
+
+        let mut volatile = vec![0u8; 0x4000];
+        // An lf list with one element at volatile offset 0x3000
+        volatile[0x268..0x278].copy_from_slice(&[
+            0xF0, 0xFF, 0xFF, 0xFF, b'l', b'f', 1, 0,
+            0x00, 0x30, 0x00, 0x80, b'C', b's', b'i', b'T'
+        ]);
+        // The 144 byte root nk cell, with a parent and no subkey or value lists
+        let (root_cell, size) = (4096 + 32, 144);
+        volatile[0x3000..0x3000 + size].copy_from_slice(&stable[root_cell..root_cell + size]);
+        let nk = 0x3000 + 4;
+        volatile[nk + 2..nk + 4].copy_from_slice(&[0x21, 0]);
+        volatile[nk + 16..nk + 20].copy_from_slice(&[32, 0, 0, 0]);
+        for field in [20, 24, 36].iter() {
+            volatile[nk + field..nk + field + 4].copy_from_slice(&[0, 0, 0, 0]);
+        }
+        for field in [28, 32, 40].iter() {
+            volatile[nk + field..nk + field + 4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        }
+
+        let hive = Hive::from_memory(Cursor::new(stable), volatile).unwrap();
+        assert!(hive.has_volatile_storage());
+
+        // The other volatile lists are not in this volatile storage
+        let mut key_records = hive.key_records().lenient();
+        let key_records: Vec<_> = key_records.by_ref()
+            .map(|result| result.unwrap())
+            .collect();
+        assert_eq!(key_records.len(), key_count + 1);
+        assert_eq!(key_records[0].num_volatile_sub_keys, 1);
+        assert!(!key_records[0].volatile);
+
+        let volatile_key = key_records.last().unwrap();
+        assert!(volatile_key.volatile);
+        assert_eq!(volatile_key.fullpath, format!("{}\\{}",key_records[0].fullpath,root.key_name()));
+
+        let volatile_subkeys = hive.get_root_key().unwrap().volatile_subkeys().unwrap();
+        assert_eq!(volatile_subkeys.len(), 1);
+        assert_eq!(volatile_subkeys[0].get_path(), volatile_key.fullpath);
+    }
+}